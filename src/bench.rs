@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::*;
+use hdrhistogram::Histogram;
+use serde_json::json;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::lazyreq::LazyReq;
+use crate::policy::RetryPolicy;
+
+const DEFAULT_REQUESTS: u64 = 100;
+const DEFAULT_CONCURRENCY: usize = 10;
+// latencies are recorded in microseconds, up to an hour
+const MAX_LATENCY: u64 = 3_600_000_000;
+
+pub struct BenchConfig {
+    pub requests: Option<u64>,
+    pub concurrency: usize,
+    pub duration: Option<Duration>,
+    pub rate: Option<f64>,
+    pub report: Option<String>,
+}
+
+struct Stats {
+    latencies: Histogram<u64>,
+    outcomes: BTreeMap<String, u64>,
+    errors: u64,
+}
+
+impl BenchConfig {
+    pub fn default() -> BenchConfig {
+        BenchConfig {
+            requests: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            duration: None,
+            rate: None,
+            report: None,
+        }
+    }
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            latencies: Histogram::new_with_bounds(1, MAX_LATENCY, 3).unwrap(),
+            outcomes: BTreeMap::new(),
+            errors: 0,
+        }
+    }
+
+    fn record(&mut self, latency: Duration, outcome: String, failed: bool) {
+        self.latencies
+            .saturating_record((latency.as_micros() as u64).max(1));
+        *self.outcomes.entry(outcome).or_insert(0) += 1;
+        if failed {
+            self.errors += 1;
+        }
+    }
+
+    fn merge(&mut self, other: Stats) {
+        self.latencies.add(&other.latencies).unwrap();
+        for (outcome, count) in other.outcomes {
+            *self.outcomes.entry(outcome).or_insert(0) += count;
+        }
+        self.errors += other.errors;
+    }
+
+    fn requests(&self) -> u64 {
+        self.latencies.len()
+    }
+
+    fn percentile_ms(&self, percentile: f64) -> f64 {
+        self.latencies.value_at_quantile(percentile / 100.0) as f64 / 1000.0
+    }
+
+    fn max_ms(&self) -> f64 {
+        self.latencies.max() as f64 / 1000.0
+    }
+}
+
+/// Parses `200/s`, `600/m` or a plain number of requests per second.
+pub fn parse_rate(value: &str) -> Option<f64> {
+    let (count, unit) = value.split_once("/").unwrap_or((value, "s"));
+    let per = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+
+    match count.trim().parse::<f64>() {
+        Ok(count) if count > 0.0 => Some(count / per),
+        _ => None,
+    }
+}
+
+/// Fires the request over and over, hooks are resolved only once beforehand.
+pub async fn run(lazyreq: Arc<LazyReq>, id: String, config: BenchConfig) {
+    let req = match lazyreq.request(&id) {
+        Some(req) => req,
+        None => lazyreq.fail(&id, "Request not found"),
+    };
+
+    let mut prepared = match lazyreq.prepare(req).await {
+        Ok(prepared) => prepared,
+        Err(e) => lazyreq.fail(&id, &e.to_string()),
+    };
+    // retries and cookies would skew what's being measured
+    prepared.retry = RetryPolicy::default();
+    prepared.use_cookies = false;
+    let prepared = Arc::new(prepared);
+
+    let total = match (config.requests, config.duration) {
+        (None, None) => Some(DEFAULT_REQUESTS),
+        (requests, _) => requests,
+    };
+    let url = lazyreq.mask(&prepared.url);
+    let issued = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let deadline = config.duration.map(|duration| start + duration);
+
+    println!(
+        "{}{}{} {}",
+        "[".bold().green(),
+        req.method.clone().bold().green(),
+        "]".bold().green(),
+        url.bold().green()
+    );
+
+    let mut workers = JoinSet::new();
+    for _ in 0..config.concurrency.max(1) {
+        let lazyreq = lazyreq.clone();
+        let prepared = prepared.clone();
+        let issued = issued.clone();
+        let rate = config.rate;
+
+        workers.spawn(async move {
+            let mut stats = Stats::new();
+
+            loop {
+                let i = issued.fetch_add(1, Ordering::SeqCst);
+                if total.is_some_and(|total| i >= total) {
+                    break;
+                }
+                if let Some(rate) = rate {
+                    let at = start + Duration::from_secs_f64(i as f64 / rate);
+                    if deadline.is_some_and(|deadline| at >= deadline) {
+                        break;
+                    }
+                    tokio::time::sleep_until(at).await;
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    break;
+                }
+
+                let sent = Instant::now();
+                let result = lazyreq
+                    .send_prepared(&prepared)
+                    .await
+                    .map_err(|e| error_kind(e.as_ref()));
+                let (outcome, failed) = match result {
+                    Ok((response, _)) => {
+                        let status = response.status();
+                        match response.bytes().await {
+                            Ok(_) => (status.to_string(), !is_success(status)),
+                            Err(e) => (error_kind(&e), true),
+                        }
+                    }
+                    Err(kind) => (kind, true),
+                };
+                stats.record(sent.elapsed(), outcome, failed);
+            }
+
+            stats
+        });
+    }
+
+    let mut stats = Stats::new();
+    while let Some(worker) = workers.join_next().await {
+        stats.merge(worker.unwrap());
+    }
+    let elapsed = start.elapsed();
+
+    print_summary(&stats, elapsed);
+
+    if let Some(report) = &config.report {
+        match write_report(report, &id, &url, &stats, elapsed) {
+            Ok(_) => println!("Report written to {}", report),
+            Err(e) => eprintln!("Failed to write report: {}", e),
+        }
+    }
+}
+
+fn is_success(status: reqwest::StatusCode) -> bool {
+    status.is_success() || status.is_redirection()
+}
+
+fn error_kind(e: &(dyn Error + 'static)) -> String {
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_timeout() => "timeout".to_string(),
+        Some(e) if e.is_connect() => "connect error".to_string(),
+        _ => "error".to_string(),
+    }
+}
+
+fn throughput(stats: &Stats, elapsed: Duration) -> f64 {
+    stats.requests() as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+fn print_summary(stats: &Stats, elapsed: Duration) {
+    let errors = format!("({} errors)", stats.errors);
+    println!(
+        "{} {} {}",
+        "Requests:".bold().green(),
+        stats.requests(),
+        match stats.errors {
+            0 => errors.green(),
+            _ => errors.red(),
+        }
+    );
+    println!(
+        "{} {:.2}s",
+        "Duration:".bold().green(),
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "{} {:.1} req/s",
+        "Throughput:".bold().green(),
+        throughput(stats, elapsed)
+    );
+
+    if stats.requests() == 0 {
+        return;
+    }
+
+    println!(
+        "{} p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms",
+        "Latency:".bold().green(),
+        stats.percentile_ms(50.0),
+        stats.percentile_ms(90.0),
+        stats.percentile_ms(99.0),
+        stats.max_ms()
+    );
+    println!("{}", "Responses:".bold().green());
+    for (outcome, count) in &stats.outcomes {
+        println!("  {} {}", outcome, count);
+    }
+}
+
+/// Writes a `.csv` or `.json` report, CSV is a single row so runs can be
+/// appended to the same file.
+fn write_report(
+    path: &str,
+    id: &str,
+    url: &str,
+    stats: &Stats,
+    elapsed: Duration,
+) -> Result<(), Box<dyn Error>> {
+    if path.ends_with(".csv") {
+        let responses = stats
+            .outcomes
+            .iter()
+            .map(|(outcome, count)| format!("{}:{}", outcome, count))
+            .collect::<Vec<String>>()
+            .join(";");
+        let row = format!(
+            "{},{},{},{},{:.3},{:.1},{:.3},{:.3},{:.3},{:.3},\"{}\"\n",
+            csv_field(id),
+            csv_field(url),
+            stats.requests(),
+            stats.errors,
+            elapsed.as_secs_f64(),
+            throughput(stats, elapsed),
+            stats.percentile_ms(50.0),
+            stats.percentile_ms(90.0),
+            stats.percentile_ms(99.0),
+            stats.max_ms(),
+            responses
+        );
+
+        let mut content = fs::read_to_string(path).unwrap_or_default();
+        if content.is_empty() {
+            content.push_str("id,url,requests,errors,duration_s,throughput,p50_ms,p90_ms,p99_ms,max_ms,responses\n");
+        }
+        content.push_str(row.as_str());
+        fs::write(path, content)?;
+    } else {
+        let report = json!({
+            "id": id,
+            "url": url,
+            "requests": stats.requests(),
+            "errors": stats.errors,
+            "duration_s": elapsed.as_secs_f64(),
+            "throughput": throughput(stats, elapsed),
+            "latency_ms": {
+                "p50": stats.percentile_ms(50.0),
+                "p90": stats.percentile_ms(90.0),
+                "p99": stats.percentile_ms(99.0),
+                "max": stats.max_ms(),
+            },
+            "responses": stats.outcomes,
+        });
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+
+    Ok(())
+}
+
+/// Quotes a CSV field when it has a comma, quote or line break in it, a URL's
+/// query often does.
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
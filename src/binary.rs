@@ -0,0 +1,283 @@
+use encoding_rs::{Encoding, UTF_8};
+use mime_guess::mime::Mime;
+use reqwest::header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_TYPE};
+
+const PREVIEW_BYTES: usize = 64;
+const SIGNATURES: [(&[u8], &str); 12] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BM", "image/bmp"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"OggS", "audio/ogg"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"wOFF", "font/woff"),
+];
+
+fn content_type(headers: &HeaderMap) -> Option<Mime> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Mime>().ok())
+}
+
+/// Decodes the body with the charset from `Content-Type`, UTF-8 when
+/// there's none, like `reqwest::Response::text` does.
+pub fn decode_text(headers: &HeaderMap, bytes: &[u8]) -> String {
+    let encoding = content_type(headers)
+        .and_then(|mime| mime.get_param("charset").map(|charset| charset.to_string()))
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(UTF_8);
+
+    encoding.decode(bytes).0.into_owned()
+}
+
+/// Whether the body shouldn't be printed as text, going by the content type
+/// and, when that doesn't say, by the bytes themselves.
+pub fn is_binary(headers: &HeaderMap, bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+
+    if let Some(mime) = content_type(headers) {
+        let textual = mime.type_() == "text"
+            || [
+                "json",
+                "xml",
+                "javascript",
+                "x-www-form-urlencoded",
+                "yaml",
+                "csv",
+            ]
+            .iter()
+            .any(|kind| mime.subtype().as_str().contains(kind))
+            || mime
+                .suffix()
+                .is_some_and(|suffix| suffix == "json" || suffix == "xml");
+        if textual {
+            return false;
+        }
+        if mime.type_() != "application" || mime.subtype() != "octet-stream" {
+            return true;
+        }
+    }
+
+    bytes.contains(&0) || std::str::from_utf8(bytes).is_err()
+}
+
+/// Type going by the first bytes of the body, if it's a known one.
+pub fn detect_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map(|(_, kind)| *kind)
+}
+
+pub fn format_size(size: usize) -> String {
+    match size {
+        size if size < 1024 => format!("{} B", size),
+        size if size < 1024 * 1024 => format!("{:.1} KB", size as f64 / 1024.0),
+        size => format!("{:.1} MB", size as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// Size, type and a hex dump of the first bytes, printed instead of a
+/// binary body.
+pub fn preview(headers: &HeaderMap, bytes: &[u8]) -> String {
+    let declared = content_type(headers).map(|mime| mime.essence_str().to_string());
+    let kind = match (detect_type(bytes), declared) {
+        (Some(detected), Some(declared)) if detected != declared => {
+            format!("{} (sent as {})", detected, declared)
+        }
+        (Some(detected), _) => detected.to_string(),
+        (None, Some(declared)) => declared,
+        (None, None) => "unknown type".to_string(),
+    };
+
+    let mut output = format!("Binary body, {}, {}\n", format_size(bytes.len()), kind);
+    for (line, chunk) in bytes[..bytes.len().min(PREVIEW_BYTES)]
+        .chunks(16)
+        .enumerate()
+    {
+        let hex = chunk
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let ascii = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect::<String>();
+        output.push_str(&format!("{:08x}  {:<47}  {}\n", line * 16, hex, ascii));
+    }
+    if bytes.len() > PREVIEW_BYTES {
+        output.push_str("...\n");
+    }
+
+    output
+}
+
+/// File name suggested by `Content-Disposition`, without any directories so
+/// a server can't write outside of the current one.
+pub fn disposition_filename(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_DISPOSITION)?.to_str().ok()?;
+
+    let mut plain = None;
+    let mut extended = None;
+    for param in value.split(";").skip(1) {
+        let Some((name, value)) = param.trim().split_once("=") else {
+            continue;
+        };
+        match name.trim().to_lowercase().as_str() {
+            // filename*=UTF-8''na%C3%AFve.txt
+            "filename*" => {
+                extended = value
+                    .split_once("''")
+                    .map(|(_, encoded)| percent_decode(encoded))
+            }
+            "filename" => plain = Some(value.trim().trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+
+    safe_filename(&extended.or(plain)?)
+}
+
+/// Last part of a path, `None` when nothing usable is left.
+pub fn safe_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+
+    Some(name.to_string())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match (
+            bytes[i],
+            hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+        ) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn disposition(value: &'static str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static(value));
+        disposition_filename(&headers)
+    }
+
+    #[test]
+    fn disposition_plain_filename() {
+        assert_eq!(
+            disposition("attachment; filename=\"report.pdf\""),
+            Some("report.pdf".to_string())
+        );
+        assert_eq!(
+            disposition("attachment; FILENAME=report.pdf"),
+            Some("report.pdf".to_string())
+        );
+    }
+
+    #[test]
+    fn disposition_prefers_the_extended_filename() {
+        assert_eq!(
+            disposition("attachment; filename=\"naive.txt\"; filename*=UTF-8''na%C3%AFve.txt"),
+            Some("naïve.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn disposition_without_a_filename() {
+        assert_eq!(disposition("inline"), None);
+        assert_eq!(disposition("attachment; name=file"), None);
+        assert_eq!(disposition_filename(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn disposition_drops_directories() {
+        assert_eq!(
+            disposition("attachment; filename=\"../../etc/passwd\""),
+            Some("passwd".to_string())
+        );
+        assert_eq!(
+            disposition("attachment; filename=\"/tmp/evil.sh\""),
+            Some("evil.sh".to_string())
+        );
+        assert_eq!(
+            disposition("attachment; filename=\"..\\\\..\\\\boot.ini\""),
+            Some("boot.ini".to_string())
+        );
+        // encoded slashes are decoded before the directories are dropped
+        assert_eq!(
+            disposition("attachment; filename*=UTF-8''..%2F..%2F.bashrc"),
+            Some(".bashrc".to_string())
+        );
+    }
+
+    #[test]
+    fn disposition_nothing_left() {
+        assert_eq!(disposition("attachment; filename=\"..\""), None);
+        assert_eq!(disposition("attachment; filename=\"dir/\""), None);
+        assert_eq!(disposition("attachment; filename*=UTF-8''..%2F.."), None);
+    }
+
+    #[test]
+    fn safe_filename_keeps_the_last_part() {
+        assert_eq!(safe_filename("file.bin"), Some("file.bin".to_string()));
+        assert_eq!(safe_filename("a/b/file.bin"), Some("file.bin".to_string()));
+        assert_eq!(
+            safe_filename("a\\b\\file.bin"),
+            Some("file.bin".to_string())
+        );
+        assert_eq!(
+            safe_filename(" spaced.txt "),
+            Some("spaced.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn safe_filename_rejects_traversal() {
+        assert_eq!(safe_filename(""), None);
+        assert_eq!(safe_filename("."), None);
+        assert_eq!(safe_filename(".."), None);
+        assert_eq!(safe_filename("../.."), None);
+        assert_eq!(safe_filename("/"), None);
+        assert_eq!(safe_filename("..\\"), None);
+    }
+}
@@ -1,104 +1,491 @@
-use crate::timest::{add_seconds, get_timestamp, is_older_than};
+use crate::crypto::{self, Sealed};
+use crate::response::Response;
+use crate::timest::{add_seconds, format_seconds, get_timestamp, is_older_than};
+use colored::*;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{to_string_pretty, Value};
+use sha2::{Digest, Sha256};
+use std::error::Error;
 use std::fs::{self, File, OpenOptions};
-use std::hash::{DefaultHasher, Hash, Hasher};
-use std::io::{self, BufRead, Seek, SeekFrom, Write};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+const CACHE_VERSION: u32 = 1;
+
+/// A cached response as it's stored on disk.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CacheEntry {
+    pub version: u32,
+    pub source: String,
+    pub environment: String,
+    pub id: String,
+    pub status: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub created_at: u64,
+    pub expires_at: u64,
+    /// Status, headers and body when the cache is encrypted, the rest stays
+    /// readable so entries can be listed and pruned without the key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Sealed>,
+    /// Whether the entry was stored encrypted, set once it's opened.
+    #[serde(skip)]
+    pub encrypted: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedResponse {
+    status: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
 
 pub struct Cache {
-    file: File,
-    pub data: Option<String>,
-    pub expire: u64,
+    path: PathBuf,
+    source: String,
+    environment: String,
+    id: String,
+    pub entry: Option<CacheEntry>,
 }
 
-fn calculate_cache_name(filename: &str, req_id: &str) -> String {
-    let mut hasher = DefaultHasher::new();
-    filename.hash(&mut hasher);
-    req_id.hash(&mut hasher);
+/// Stable name for a key made of several parts, the same parts give the same
+/// name across runs and lazyreq versions.
+pub fn calculate_cache_name(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
 
-    return format!("{:x}", hasher.finish());
+    hex::encode(hasher.finalize())
 }
 
-fn get_lazyreq_dir() -> PathBuf {
+pub fn get_lazyreq_dir() -> PathBuf {
     home::home_dir()
         .expect("Failed to retrieve home directory")
         .join(".lazyreq")
 }
 
-fn setup_directories() -> std::io::Result<PathBuf> {
-    let base_dir = get_lazyreq_dir();
+fn get_cache_dir() -> PathBuf {
+    get_lazyreq_dir().join("cache")
+}
+
+/// Absolute path of a `.lreq` file, used to tell where stored data came from.
+pub fn canonical_source(filename: &str) -> String {
+    fs::canonicalize(filename)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or(filename.to_string())
+}
 
-    let cache_dir = base_dir.join("cache");
-    fs::create_dir_all(&cache_dir)?;
+/// Creates (or truncates) a file only the current user can read.
+pub fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+    #[cfg(unix)]
+    options.mode(0o600);
 
-    Ok(base_dir)
+    options.open(path)
 }
 
-fn find_file(filename: &str, req_id: &str) -> (bool, File) {
-    let cache_name = calculate_cache_name(filename, req_id);
+/// Locks the sidecar `.lock` file of an entry, so concurrent lazyreq
+/// processes never see each other's half-finished writes.
+fn lock(path: &Path, exclusive: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(false).write(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let file = options.open(path.with_extension("lock"))?;
+
+    match exclusive {
+        true => file.lock()?,
+        false => file.lock_shared()?,
+    }
 
-    let path: String = get_lazyreq_dir().to_string_lossy().to_string() + "/cache";
-    let cache_file = format!("{}/{}", path.clone(), cache_name);
+    Ok(file)
+}
 
-    if Path::new(&cache_file).exists() {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(cache_file)
-            .unwrap();
+/// Reads an entry, anything missing, unreadable or from another format
+/// version counts as a miss.
+fn read_entry(path: &Path) -> Option<CacheEntry> {
+    if !path.exists() {
+        return None;
+    }
 
-        return (false, file);
+    let _lock = lock(path, false).ok()?;
+    let content = fs::read_to_string(path).ok()?;
+    let mut entry: CacheEntry = serde_json::from_str(content.as_str()).ok()?;
+    if entry.version != CACHE_VERSION {
+        return None;
     }
 
-    match setup_directories() {
-        Ok(_) => {}
-        Err(e) => panic!("Failed to set up directories: {}", e),
+    // without the right key the entry stays sealed and counts as a miss
+    let opened = match (&entry.sealed, crypto::cache_key()) {
+        (Some(sealed), Some(key)) => crypto::open(key, sealed).ok(),
+        _ => None,
+    };
+    if let Some(opened) = opened {
+        let response: SealedResponse = serde_json::from_slice(&opened).ok()?;
+        entry.status = response.status;
+        entry.headers = response.headers;
+        entry.body = response.body;
+        entry.sealed = None;
+        entry.encrypted = true;
     }
 
-    return (true, File::create_new(&cache_file).unwrap());
+    Some(entry)
 }
 
-impl Cache {
-    pub fn new(filename: &str, req_id: &str) -> Cache {
-        let (is_new, f) = find_file(filename, req_id);
-        if is_new {
-            return Cache {
-                file: f,
-                data: None,
-                expire: 0,
-            };
-        }
+/// Encrypts status, headers and body when a cache key is configured.
+fn seal_entry(entry: &CacheEntry) -> Result<CacheEntry, Box<dyn Error>> {
+    let key = match crypto::cache_key() {
+        Some(key) => key,
+        None => return Ok(entry.clone()),
+    };
+
+    let response = SealedResponse {
+        status: entry.status.clone(),
+        headers: entry.headers.clone(),
+        body: entry.body.clone(),
+    };
+    let sealed = crypto::seal(key, serde_json::to_string(&response)?.as_bytes())?;
+
+    Ok(CacheEntry {
+        status: String::new(),
+        headers: Vec::new(),
+        body: String::new(),
+        sealed: Some(sealed),
+        ..entry.clone()
+    })
+}
+
+/// Writes the entry to a temporary file first and renames it in place, a
+/// crash mid-write leaves the previous entry untouched.
+fn write_entry(path: &Path, entry: &CacheEntry) -> Result<(), Box<dyn Error>> {
+    let stored = seal_entry(entry)?;
+
+    fs::create_dir_all(get_cache_dir())?;
+    let _lock = lock(path, true)?;
 
-        let reader = io::BufReader::new(f.try_clone().unwrap());
-        let mut lines = reader.lines();
-        let first_line = lines.next().unwrap();
-        let second_line = lines.next().unwrap();
+    let temp = path.with_extension(format!("{}.tmp", process::id()));
+    let mut file = create_private(&temp)?;
+    file.write_all(serde_json::to_string(&stored)?.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+impl Cache {
+    /// Opens the entry for a request, `definition` is what the request looks
+    /// like once resolved so any change to it makes for a new entry.
+    pub fn new(filename: &str, environment: &str, req_id: &str, definition: &str) -> Cache {
+        let source = canonical_source(filename);
+        let name = calculate_cache_name(&[source.as_str(), environment, req_id, definition]);
+        let path = get_cache_dir().join(name);
+        let entry = read_entry(&path);
 
         Cache {
-            file: f,
-            data: Some(second_line.unwrap().to_string()),
-            expire: first_line.unwrap().to_string().parse::<u64>().unwrap(),
+            path,
+            source,
+            environment: environment.to_string(),
+            id: req_id.to_string(),
+            entry,
         }
     }
 
+    /// With a cache key, entries stored in plain text are misses so they get
+    /// written again encrypted.
     pub fn get(&mut self) -> Option<String> {
-        if self.data.is_some() {
-            if is_older_than(self.expire) {
-                return None;
+        match &self.entry {
+            Some(entry)
+                if entry.sealed.is_none()
+                    && (entry.encrypted || crypto::cache_key().is_none())
+                    && !is_older_than(entry.expires_at) =>
+            {
+                Some(entry.body.clone())
             }
+            _ => None,
+        }
+    }
 
-            return self.data.clone();
+    /// Like `get`, but with the status and headers that came with the body.
+    pub fn get_response(&mut self) -> Option<Response> {
+        let body = self.get()?;
+        let entry = self.entry.as_ref().unwrap();
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &entry.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
         }
 
-        return None;
+        // entries from `set` have no status, they're only cached when good
+        let status = entry
+            .status
+            .split(" ")
+            .next()
+            .and_then(|code| code.parse::<u16>().ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::OK);
+
+        Some(Response {
+            method: String::new(),
+            status,
+            url: String::new(),
+            sent_headers: HeaderMap::new(),
+            headers,
+            bytes: body.clone().into_bytes(),
+            body,
+            redirects: Vec::new(),
+            generated: Vec::new(),
+            elapsed: Duration::ZERO,
+        })
+    }
+
+    /// Returns the cached value even if it already expired.
+    pub fn peek(&self) -> Option<String> {
+        match &self.entry {
+            Some(entry) if entry.sealed.is_none() => Some(entry.body.clone()),
+            _ => None,
+        }
     }
 
     pub fn set(&mut self, value: String, expire_in_seconds: u64) {
-        let expired_at = add_seconds(get_timestamp(), expire_in_seconds);
+        self.set_response(String::new(), &HeaderMap::new(), value, expire_in_seconds);
+    }
+
+    pub fn set_response(
+        &mut self,
+        status: String,
+        headers: &HeaderMap,
+        body: String,
+        expire_in_seconds: u64,
+    ) {
+        // an encrypted entry is never replaced by a plain text one
+        if self
+            .entry
+            .as_ref()
+            .is_some_and(|entry| entry.sealed.is_some())
+            && crypto::cache_key().is_none()
+        {
+            eprintln!(
+                "Not caching {}, its cache entry is encrypted and no cache key is set",
+                self.id
+            );
+            return;
+        }
 
-        self.file.set_len(0).unwrap();
-        self.file.seek(SeekFrom::Start(0)).unwrap();
-        self.file
-            .write_all(format!("{}\n{}\n", expired_at, value).as_bytes())
-            .unwrap();
+        let created_at = get_timestamp();
+        let entry = CacheEntry {
+            version: CACHE_VERSION,
+            source: self.source.clone(),
+            environment: self.environment.clone(),
+            id: self.id.clone(),
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        value.to_str().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect(),
+            body,
+            created_at,
+            expires_at: add_seconds(created_at, expire_in_seconds),
+            sealed: None,
+            encrypted: crypto::cache_key().is_some(),
+        };
+
+        // a cache that can't be written only costs a request next time
+        if let Err(e) = write_entry(&self.path, &entry) {
+            eprintln!("Failed to write cache: {}", e);
+        }
+        self.entry = Some(entry);
     }
 }
+
+/// Every entry in the cache directory, unreadable ones come back as `None`.
+fn stored_entries() -> Vec<(PathBuf, Option<CacheEntry>)> {
+    let entries = match fs::read_dir(get_cache_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    // lock and temporary files are the only ones with an extension
+    let mut stored: Vec<(PathBuf, Option<CacheEntry>)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_none())
+        .map(|path| {
+            let entry = read_entry(&path);
+            (path, entry)
+        })
+        .collect();
+
+    stored.sort_by_key(|(_, entry)| {
+        entry
+            .as_ref()
+            .map(|e| (e.source.clone(), e.id.clone(), e.environment.clone()))
+    });
+    stored
+}
+
+/// Readable entries, restricted to the given file and request id when set.
+fn find_entries(filename: &str, id: &str) -> Vec<(PathBuf, CacheEntry)> {
+    let source = canonical_source(filename);
+
+    stored_entries()
+        .into_iter()
+        .filter_map(|(path, entry)| entry.map(|entry| (path, entry)))
+        .filter(|(_, entry)| filename.is_empty() || entry.source == source)
+        .filter(|(_, entry)| id.is_empty() || entry.id == id)
+        .collect()
+}
+
+fn remove_entry(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)?;
+    // the lock file may never have been created
+    let _ = fs::remove_file(path.with_extension("lock"));
+    Ok(())
+}
+
+fn format_expiry(entry: &CacheEntry) -> String {
+    let now = get_timestamp();
+    match is_older_than(entry.expires_at) {
+        true => format!("expired {} ago", format_seconds(now - entry.expires_at)),
+        false => format!("expires in {}", format_seconds(entry.expires_at - now)),
+    }
+}
+
+pub fn list(filename: &str) {
+    let entries = find_entries(filename, "");
+    if entries.is_empty() {
+        println!("No cached entries");
+        return;
+    }
+
+    let mut source = String::new();
+    for (path, entry) in entries {
+        if entry.source != source {
+            source = entry.source.clone();
+            println!("{}", source.bold().green());
+        }
+
+        let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        let id = match entry.environment.is_empty() {
+            true => entry.id.clone(),
+            false => format!("{} ({})", entry.id, entry.environment),
+        };
+        println!(
+            "  {} {} {} {} {} B",
+            id.bold(),
+            "age:".dimmed(),
+            format_seconds(get_timestamp().saturating_sub(entry.created_at)),
+            format_expiry(&entry),
+            size
+        );
+    }
+}
+
+pub fn show(filename: &str, id: &str) {
+    // the latest one, older definitions of the request may still be around
+    let latest = find_entries(filename, id)
+        .into_iter()
+        .max_by_key(|(_, entry)| entry.created_at);
+    let entry = match latest {
+        Some((_, entry)) => entry,
+        None => {
+            println!("No cached entry for {}", id);
+            return;
+        }
+    };
+
+    println!(
+        "{} {}",
+        format!("[{}]", entry.id).bold().green(),
+        entry.source.bold().green()
+    );
+    if !entry.status.is_empty() {
+        println!("{} {}", "Status:".bold().green(), entry.status);
+    }
+    println!(
+        "{} {} ago, {}",
+        "Cached:".bold().green(),
+        format_seconds(get_timestamp().saturating_sub(entry.created_at)),
+        format_expiry(&entry)
+    );
+    if entry.sealed.is_some() {
+        println!(
+            "{}",
+            "Encrypted, the cache key isn't set or doesn't match".yellow()
+        );
+        return;
+    }
+    for (name, value) in &entry.headers {
+        println!("  {}: {}", name.dimmed(), value);
+    }
+
+    let pretty_json: Value = serde_json::from_str(entry.body.as_str()).unwrap_or(Value::Null);
+    if !pretty_json.is_null() {
+        println!("{}", to_string_pretty(&pretty_json).unwrap());
+    } else {
+        println!("{}", entry.body);
+    }
+}
+
+pub fn clear(filename: &str, id: &str) {
+    let mut cleared = 0;
+
+    if filename.is_empty() {
+        // unreadable entries go as well when clearing everything
+        for (path, _) in stored_entries() {
+            match remove_entry(&path) {
+                Ok(_) => cleared += 1,
+                Err(e) => eprintln!("Failed to clear {}: {}", path.display(), e),
+            }
+        }
+    } else {
+        for (path, entry) in find_entries(filename, id) {
+            match remove_entry(&path) {
+                Ok(_) => cleared += 1,
+                Err(e) => eprintln!("Failed to clear {}: {}", entry.id, e),
+            }
+        }
+    }
+
+    println!("Cleared {} cache entries", cleared);
+}
+
+/// Removes expired and unreadable entries.
+pub fn prune() {
+    let mut pruned = 0;
+
+    for (path, entry) in stored_entries() {
+        let expired = match &entry {
+            Some(entry) => is_older_than(entry.expires_at),
+            None => true,
+        };
+        if !expired {
+            continue;
+        }
+
+        match remove_entry(&path) {
+            Ok(_) => pruned += 1,
+            Err(e) => eprintln!("Failed to prune {}: {}", path.display(), e),
+        }
+    }
+
+    println!("Pruned {} cache entries", pruned);
+}
@@ -1,7 +1,37 @@
+use crate::bench::{parse_rate, BenchConfig};
+use crate::output::OutputFormat;
+use crate::policy::RedirectPolicy;
+use crate::proxy::ProxyConfig;
+use crate::timest::parse_duration;
+use crate::tls::TlsConfig;
+
+const DEFAULT_JOBS: usize = 4;
+
+pub enum Command {
+    Request,
+    Cookies(String),
+    Cache(String),
+    Bench,
+}
+
 pub struct Config {
+    pub command: Command,
     pub filename: String,
-    pub target: String,
+    pub targets: Vec<String>,
+    pub all: bool,
+    pub jobs: usize,
     pub export_curl: bool,
+    pub show_secrets: bool,
+    pub verbose: bool,
+    pub no_input: bool,
+    pub output: OutputFormat,
+    pub output_file: Option<String>,
+    pub remote_name: bool,
+    pub tls: TlsConfig,
+    pub proxy: ProxyConfig,
+    pub environment: String,
+    pub redirect: Option<RedirectPolicy>,
+    pub bench: BenchConfig,
 }
 
 impl Config {
@@ -10,30 +40,190 @@ impl Config {
             panic!("not enough arguments");
         }
 
+        let mut command = Command::Request;
         let mut export_curl = false;
+        let mut show_secrets = false;
+        let mut verbose = false;
+        let mut no_input = false;
+        let mut output = OutputFormat::Text;
+        let mut output_file = None;
+        let mut remote_name = false;
         let mut filename = String::new();
-        let mut target = String::new();
+        let mut targets = Vec::new();
+        let mut all = false;
+        let mut jobs = DEFAULT_JOBS;
+        let mut tls = TlsConfig::default();
+        let mut proxy = ProxyConfig::default();
+        let mut environment = String::new();
+        let mut redirect = None;
+        let mut bench = BenchConfig::default();
 
         let mut i = 1;
+        if args[1] == "cookies" {
+            if args[2] != "list" && args[2] != "clear" {
+                panic!("invalid cookies command provided {}", args[2]);
+            }
+            command = Command::Cookies(args[2].clone());
+            i = 3;
+        } else if args[1] == "cache" {
+            if !["list", "show", "clear", "prune"].contains(&args[2].as_str()) {
+                panic!("invalid cache command provided {}", args[2]);
+            }
+            command = Command::Cache(args[2].clone());
+            i = 3;
+        } else if args[1] == "bench" {
+            command = Command::Bench;
+            i = 2;
+        }
+
         while i < args.len() {
             if args[i] == "--curl" {
                 export_curl = true;
+            } else if args[i] == "--verbose" || args[i] == "-v" {
+                verbose = true;
+            } else if args[i] == "--no-input" {
+                no_input = true;
+            } else if args[i] == "--output" {
+                let value = flag_value(args, &mut i);
+                output = match OutputFormat::parse(value.as_str()) {
+                    Some(output) => output,
+                    None => panic!(
+                        "invalid output provided {}, use text, raw, json or ndjson",
+                        value
+                    ),
+                };
+            } else if args[i] == "--output-file" || args[i] == "-o" {
+                output_file = Some(flag_value(args, &mut i));
+            } else if args[i] == "-O" {
+                remote_name = true;
+            } else if args[i] == "--show-secrets" {
+                show_secrets = true;
+            } else if args[i] == "--all" {
+                all = true;
+            } else if args[i] == "--jobs" || args[i] == "-j" {
+                let value = flag_value(args, &mut i);
+                jobs = match value.parse::<usize>() {
+                    Ok(jobs) if jobs > 0 => jobs,
+                    _ => panic!("invalid jobs provided {}", value),
+                };
+            } else if args[i] == "--env" {
+                environment = flag_value(args, &mut i);
+            } else if args[i] == "--no-redirects" {
+                redirect = Some(RedirectPolicy::parse("off"));
+            } else if args[i] == "--max-redirects" {
+                redirect = Some(RedirectPolicy::parse(flag_value(args, &mut i).as_str()));
+            } else if args[i] == "-n" {
+                let value = flag_value(args, &mut i);
+                bench.requests = match value.parse::<u64>() {
+                    Ok(requests) if requests > 0 => Some(requests),
+                    _ => panic!("invalid number of requests provided {}", value),
+                };
+            } else if args[i] == "-c" {
+                let value = flag_value(args, &mut i);
+                bench.concurrency = match value.parse::<usize>() {
+                    Ok(concurrency) if concurrency > 0 => concurrency,
+                    _ => panic!("invalid concurrency provided {}", value),
+                };
+            } else if args[i] == "--duration" {
+                let value = flag_value(args, &mut i);
+                bench.duration = match parse_duration(value.as_str()) {
+                    Some(duration) => Some(duration),
+                    None => panic!("invalid duration provided {}", value),
+                };
+            } else if args[i] == "--rate" {
+                let value = flag_value(args, &mut i);
+                bench.rate = match parse_rate(value.as_str()) {
+                    Some(rate) => Some(rate),
+                    None => panic!("invalid rate provided {}", value),
+                };
+            } else if args[i] == "--report" {
+                let value = flag_value(args, &mut i);
+                if !value.ends_with(".csv") && !value.ends_with(".json") {
+                    panic!(
+                        "invalid report provided {}, use a .csv or .json file",
+                        value
+                    );
+                }
+                bench.report = Some(value);
+            } else if args[i] == "--proxy" {
+                proxy.url = Some(flag_value(args, &mut i));
+            } else if args[i] == "--no-proxy" {
+                proxy.no_proxy = Some(flag_value(args, &mut i));
+            } else if args[i] == "--insecure" || args[i] == "-k" {
+                tls.insecure = Some(true);
+            } else if args[i] == "--cacert" {
+                tls.ca_bundle = Some(flag_value(args, &mut i));
+            } else if args[i] == "--cert" {
+                tls.cert = Some(flag_value(args, &mut i));
+            } else if args[i] == "--key" {
+                tls.key = Some(flag_value(args, &mut i));
+            } else if args[i] == "--cert-password" {
+                tls.cert_password = Some(flag_value(args, &mut i));
+            } else if args[i] == "--tls-min" {
+                tls.min_version = Some(flag_value(args, &mut i));
             } else if filename.is_empty() {
                 filename = args[i].clone();
-            } else if target.is_empty() {
-                target = args[i].clone();
+            } else {
+                targets.push(args[i].clone());
             }
             i += 1;
         }
 
-        if filename.is_empty() || target.is_empty() {
+        let needs_target = matches!(command, Command::Request);
+        if needs_target && (filename.is_empty() || (targets.is_empty() && !all)) {
             panic!("invalid arguments");
         }
 
-        if !filename.ends_with(".lreq") {
+        if let Command::Cache(action) = &command {
+            let valid = match action.as_str() {
+                "show" => !filename.is_empty() && targets.len() == 1,
+                "clear" => targets.len() <= 1,
+                _ => targets.is_empty(),
+            };
+            if !valid {
+                panic!("invalid arguments for cache {}", action);
+            }
+        }
+
+        // every request would overwrite the same file
+        if output_file.is_some() && (all || targets.len() > 1 || remote_name) {
+            panic!("--output-file needs a single request and can't be used with -O");
+        }
+
+        if matches!(command, Command::Bench) && (filename.is_empty() || targets.len() != 1) {
+            panic!("bench needs a file and a single request");
+        }
+
+        if !filename.is_empty() && !filename.ends_with(".lreq") {
             panic!("invalid filename provided");
         }
 
-        Config { filename, target, export_curl }
+        Config {
+            command,
+            filename,
+            targets,
+            all,
+            jobs,
+            export_curl,
+            show_secrets,
+            verbose,
+            no_input,
+            output,
+            output_file,
+            remote_name,
+            tls,
+            proxy,
+            environment,
+            redirect,
+            bench,
+        }
+    }
+}
+
+fn flag_value(args: &[String], i: &mut usize) -> String {
+    *i += 1;
+    match args.get(*i) {
+        Some(value) => value.clone(),
+        None => panic!("missing value for {}", args[*i - 1]),
     }
 }
@@ -0,0 +1,212 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use colored::*;
+use cookie_store::{CookieStore, RawCookie};
+use reqwest::header::{HeaderMap, SET_COOKIE};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{calculate_cache_name, canonical_source, create_private, get_lazyreq_dir};
+
+/// Cookies shared by every request of a run, optionally persisted under
+/// `~/.lazyreq/cookies` for the file and environment.
+pub struct CookieJar {
+    store: Mutex<CookieStore>,
+    path: Option<PathBuf>,
+    origin: JarOrigin,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JarOrigin {
+    source: String,
+    environment: String,
+}
+
+fn get_cookies_dir() -> PathBuf {
+    get_lazyreq_dir().join("cookies")
+}
+
+fn jar_path(filename: &str, environment: &str) -> PathBuf {
+    let name = calculate_cache_name(&[filename, "$cookies", environment]);
+    get_cookies_dir().join(name)
+}
+
+fn read_jar(path: &PathBuf) -> io::Result<(JarOrigin, CookieStore)> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let origin: JarOrigin = serde_json::from_str(header.as_str())?;
+
+    let store = CookieStore::load_json(reader)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    Ok((origin, store))
+}
+
+impl CookieJar {
+    pub fn new() -> CookieJar {
+        CookieJar {
+            store: Mutex::new(CookieStore::default()),
+            path: None,
+            origin: JarOrigin {
+                source: "".to_string(),
+                environment: "".to_string(),
+            },
+        }
+    }
+
+    pub fn persisted(filename: &str, environment: &str) -> CookieJar {
+        let source = canonical_source(filename);
+        let path = jar_path(source.as_str(), environment);
+
+        // a missing or unreadable jar just starts empty
+        let store = read_jar(&path).map(|(_, store)| store).unwrap_or_default();
+
+        CookieJar {
+            store: Mutex::new(store),
+            path: Some(path),
+            origin: JarOrigin {
+                source,
+                environment: environment.to_string(),
+            },
+        }
+    }
+
+    /// Returns the `Cookie` header value for the url, if any cookie matches.
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let store = self.store.lock().unwrap();
+        let cookies = store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>();
+
+        if cookies.is_empty() {
+            return None;
+        }
+
+        Some(cookies.join("; "))
+    }
+
+    pub fn store(&self, url: &Url, headers: &HeaderMap) {
+        let cookies = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_string()).ok())
+            .collect::<Vec<RawCookie<'static>>>();
+
+        if cookies.is_empty() {
+            return;
+        }
+
+        self.store
+            .lock()
+            .unwrap()
+            .store_response_cookies(cookies.into_iter(), url);
+
+        if let Err(e) = self.save() {
+            eprintln!("Failed to save cookies: {}", e);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        fs::create_dir_all(get_cookies_dir())?;
+        let mut file = create_private(path)?;
+        writeln!(file, "{}", serde_json::to_string(&self.origin)?)?;
+
+        // session cookies are kept as well, they're what most login flows rely on
+        self.store
+            .lock()
+            .unwrap()
+            .save_incl_expired_and_nonpersistent_json(&mut file)
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+/// Persisted jars, restricted to the given file and environment when set.
+fn find_jars(filename: &str, environment: &str) -> Vec<(PathBuf, JarOrigin, CookieStore)> {
+    if !filename.is_empty() {
+        let path = jar_path(canonical_source(filename).as_str(), environment);
+        return match read_jar(&path) {
+            Ok((origin, store)) => vec![(path, origin, store)],
+            Err(_) => Vec::new(),
+        };
+    }
+
+    let entries = match fs::read_dir(get_cookies_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            read_jar(&path)
+                .ok()
+                .map(|(origin, store)| (path, origin, store))
+        })
+        .collect()
+}
+
+pub fn list(filename: &str, environment: &str) {
+    let jars = find_jars(filename, environment);
+    if jars.is_empty() {
+        println!("No cookies stored");
+        return;
+    }
+
+    for (_, origin, store) in jars {
+        let environment = if origin.environment.is_empty() {
+            "default".to_string()
+        } else {
+            origin.environment
+        };
+        println!(
+            "{} {}",
+            origin.source.bold().green(),
+            format!("({})", environment).green()
+        );
+
+        for cookie in store.iter_unexpired() {
+            let expires = match cookie.expires_datetime() {
+                Some(expires) => expires.to_string(),
+                None => "session".to_string(),
+            };
+            println!(
+                "  {}={} {} {}{} {} {}",
+                cookie.name().bold(),
+                cookie.value(),
+                "at:".dimmed(),
+                String::from(&cookie.domain),
+                String::from(&cookie.path),
+                "expires:".dimmed(),
+                expires
+            );
+        }
+    }
+}
+
+pub fn clear(filename: &str, environment: &str) {
+    let jars = find_jars(filename, environment);
+
+    for (path, origin, _) in &jars {
+        match fs::remove_file(path) {
+            Ok(_) => println!("Cleared cookies for {}", origin.source),
+            Err(e) => eprintln!("Failed to clear cookies for {}: {}", origin.source, e),
+        }
+    }
+
+    if jars.is_empty() {
+        println!("No cookies stored");
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::sync::OnceLock;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cache::get_lazyreq_dir;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Data encrypted with AES-256-GCM, the tag is appended to `data`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Sealed {
+    pub nonce: String,
+    pub data: String,
+}
+
+/// Key used to encrypt the cache, taken from `LAZYREQ_CACHE_KEY`, the file
+/// in `LAZYREQ_CACHE_KEY_FILE` or `~/.lazyreq/cache.key`, in that order.
+/// Any passphrase works, it's hashed down to 32 bytes.
+pub fn cache_key() -> Option<&'static [u8; 32]> {
+    static KEY: OnceLock<Option<[u8; 32]>> = OnceLock::new();
+
+    KEY.get_or_init(|| {
+        let secret = match env::var("LAZYREQ_CACHE_KEY") {
+            Ok(secret) if !secret.is_empty() => secret,
+            _ => match env::var("LAZYREQ_CACHE_KEY_FILE") {
+                Ok(path) if !path.is_empty() => fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Failed to read cache key {}: {}", path, e)),
+                _ => fs::read_to_string(get_lazyreq_dir().join("cache.key")).ok()?,
+            },
+        };
+
+        Some(Sha256::digest(secret.trim().as_bytes()).into())
+    })
+    .as_ref()
+}
+
+pub fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Sealed, Box<dyn Error>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut tag = [0u8; TAG_LEN];
+    let mut data = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[],
+        plaintext,
+        &mut tag,
+    )?;
+    data.extend_from_slice(&tag);
+
+    Ok(Sealed {
+        nonce: STANDARD.encode(nonce),
+        data: STANDARD.encode(data),
+    })
+}
+
+/// Fails on a wrong key as well as on tampered data.
+pub fn open(key: &[u8; 32], sealed: &Sealed) -> Result<Vec<u8>, Box<dyn Error>> {
+    let nonce = STANDARD.decode(&sealed.nonce)?;
+    let data = STANDARD.decode(&sealed.data)?;
+    if data.len() < TAG_LEN {
+        return Err("sealed data is too short".into());
+    }
+
+    let (ciphertext, tag) = data.split_at(data.len() - TAG_LEN);
+    Ok(decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[],
+        ciphertext,
+        tag,
+    )?)
+}
@@ -0,0 +1,122 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use regex::{Captures, Regex};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::timest::{format_iso8601, get_timestamp, parse_duration};
+
+const BUILTINS: [&str; 7] = [
+    "uuid",
+    "now",
+    "randomInt",
+    "randomString",
+    "base64",
+    "urlencode",
+    "sha256",
+];
+
+// a private use character, it shouldn't show up in real values
+const ESCAPED_DOLLAR: char = '\u{E000}';
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+/// Hides the `$` in a value that's put into a request, so nothing in a hook
+/// response or an environment variable is taken for `$env.`, `$file.`,
+/// `$prompt.` or a dynamic value. See `unescape`.
+pub fn escape(value: &str) -> String {
+    value.replace('$', ESCAPED_DOLLAR.encode_utf8(&mut [0; 4]))
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace(ESCAPED_DOLLAR, "$")
+}
+
+/// Replaces the built-in dynamic values in the text, every generated value
+/// is added to `generated` so it can be shown afterwards.
+///
+/// `$uuid`, `$now` (or `$now.iso`), `$now.unix`, with an optional offset
+/// like `$now+1d` or `$now.unix-30m`, `$randomInt(1,100)`,
+/// `$randomString(16)` and the `$base64(..)`, `$urlencode(..)` and
+/// `$sha256(..)` encoders.
+pub fn resolve(text: &str, generated: &mut Vec<(String, String)>) -> String {
+    let uuid_re = Regex::new(r"\$uuid\b").unwrap();
+    let now_re = Regex::new(r"\$now\b(\.iso|\.unix)?(?:([+-])(\d+(?:ms|[smhd])?))?").unwrap();
+    let int_re = Regex::new(r"\$randomInt\(\s*(-?\d+)\s*,\s*(-?\d+)\s*\)").unwrap();
+    let string_re = Regex::new(r"\$randomString\(\s*(\d+)\s*\)").unwrap();
+    let encoder_re = Regex::new(r"\$(base64|urlencode|sha256)\(([^()]*)\)").unwrap();
+
+    let mut record = |captures: &Captures, value: String| {
+        generated.push((captures[0].to_string(), value.clone()));
+        value
+    };
+
+    let text = uuid_re.replace_all(text, |captures: &Captures| {
+        record(captures, Uuid::new_v4().to_string())
+    });
+
+    let text = now_re.replace_all(&text, |captures: &Captures| {
+        let offset = captures
+            .get(3)
+            .map(|offset| match parse_duration(offset.as_str()) {
+                Some(offset) => offset.as_secs() as i64,
+                None => panic!("invalid offset provided {}", &captures[0]),
+            })
+            .unwrap_or(0);
+        let sign = match captures.get(2).map(|sign| sign.as_str()) {
+            Some("-") => -1,
+            _ => 1,
+        };
+        let timestamp = (get_timestamp() as i64 + sign * offset).max(0) as u64;
+
+        let value = match captures.get(1).map(|format| format.as_str()) {
+            Some(".unix") => timestamp.to_string(),
+            _ => format_iso8601(timestamp),
+        };
+        record(captures, value)
+    });
+
+    let text = int_re.replace_all(&text, |captures: &Captures| {
+        let min = captures[1].parse::<i64>().unwrap();
+        let max = captures[2].parse::<i64>().unwrap();
+        if min > max {
+            panic!("invalid range provided {}", &captures[0]);
+        }
+        record(
+            captures,
+            rand::thread_rng().gen_range(min..=max).to_string(),
+        )
+    });
+
+    let text = string_re.replace_all(&text, |captures: &Captures| {
+        let length = captures[1].parse::<usize>().unwrap();
+        let value = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(length)
+            .map(char::from)
+            .collect::<String>();
+        record(captures, value)
+    });
+
+    // innermost first, so encoders can be nested
+    let mut text = text.to_string();
+    while encoder_re.is_match(&text) {
+        text = encoder_re
+            .replace_all(&text, |captures: &Captures| {
+                // encoders work on the value as it'll be sent
+                let input = unescape(&captures[2]);
+                match &captures[1] {
+                    "base64" => STANDARD.encode(&input),
+                    "urlencode" => auth::uri_encode(&input, true),
+                    _ => auth::sha256_hex(input.as_bytes()),
+                }
+            })
+            .to_string();
+    }
+
+    text
+}
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::request::Request;
+
+/// Returns the requests a request runs through its hooks, directly or
+/// through other hooked requests.
+fn hooked_requests(
+    id: &str,
+    requests: &HashMap<String, Request>,
+    hooks: &HashMap<String, String>,
+    seen: &mut HashSet<String>,
+) {
+    let req = match requests.get(id) {
+        Some(req) => req,
+        None => return,
+    };
+
+    for target in hook_targets(req, hooks) {
+        if seen.insert(target.clone()) {
+            hooked_requests(&target, requests, hooks, seen);
+        }
+    }
+}
+
+/// Returns the requests a request runs directly through its hooks.
+fn hook_targets(req: &Request, hooks: &HashMap<String, String>) -> Vec<String> {
+    req.references()
+        .iter()
+        .filter_map(|reference| hooks.get(reference))
+        .map(|hook| hook.split(" ").collect::<Vec<&str>>()[0].replace("$req.", ""))
+        .collect()
+}
+
+/// Returns the chain of requests leading back to one already in `chain`, if
+/// any hook ends up calling into itself.
+fn find_cycle_from(
+    id: &str,
+    requests: &HashMap<String, Request>,
+    hooks: &HashMap<String, String>,
+    chain: &mut Vec<String>,
+    done: &mut HashSet<String>,
+) -> Option<Vec<String>> {
+    if let Some(start) = chain.iter().position(|seen| seen == id) {
+        let mut cycle = chain[start..].to_vec();
+        cycle.push(id.to_string());
+        return Some(cycle);
+    }
+    if done.contains(id) {
+        return None;
+    }
+
+    let req = requests.get(id)?;
+    chain.push(id.to_string());
+    for target in hook_targets(req, hooks) {
+        if let Some(cycle) = find_cycle_from(&target, requests, hooks, chain, done) {
+            return Some(cycle);
+        }
+    }
+    chain.pop();
+    done.insert(id.to_string());
+
+    None
+}
+
+/// Returns the first hook cycle reachable from the request, e.g.
+/// `["a", "b", "a"]` when `a` hooks into `b` and `b` back into `a`.
+pub fn find_cycle(
+    id: &str,
+    requests: &HashMap<String, Request>,
+    hooks: &HashMap<String, String>,
+) -> Option<Vec<String>> {
+    find_cycle_from(id, requests, hooks, &mut Vec::new(), &mut HashSet::new())
+}
+
+/// Maps every id to the other ids of the run it has to wait for: the
+/// requests it hooks into, and the requests before it that set a script
+/// variable it (or a request it hooks into) uses, like they would with `-j 1`.
+pub fn dependencies(
+    requests: &HashMap<String, Request>,
+    hooks: &HashMap<String, String>,
+    ids: &[String],
+) -> HashMap<String, HashSet<String>> {
+    let selected: HashSet<&String> = ids.iter().collect();
+
+    let hooked: Vec<HashSet<String>> = ids
+        .iter()
+        .map(|id| {
+            let mut seen = HashSet::new();
+            hooked_requests(id, requests, hooks, &mut seen);
+            seen
+        })
+        .collect();
+    let script_vars: Vec<Vec<String>> = ids
+        .iter()
+        .map(|id| {
+            requests
+                .get(id)
+                .map(Request::script_vars)
+                .unwrap_or_default()
+        })
+        .collect();
+
+    ids.iter()
+        .enumerate()
+        .map(|(index, id)| {
+            let used: HashSet<String> = hooked[index]
+                .iter()
+                .chain([id])
+                .filter_map(|id| requests.get(id))
+                .flat_map(Request::references)
+                .collect();
+
+            // unless the earlier one hooks into this one, that would wait forever
+            let setters = (0..index).filter(|&earlier| {
+                !hooked[earlier].contains(id)
+                    && script_vars[earlier].iter().any(|name| used.contains(name))
+            });
+
+            let deps = hooked[index]
+                .iter()
+                .chain(setters.map(|earlier| &ids[earlier]))
+                .filter(|dep| *dep != id && selected.contains(dep))
+                .cloned()
+                .collect();
+            (id.clone(), deps)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str) -> Request {
+        Request::new(
+            "GET".to_string(),
+            path.to_string(),
+            String::new(),
+            Vec::new(),
+        )
+    }
+
+    fn requests(paths: &[(&str, &str)]) -> HashMap<String, Request> {
+        paths
+            .iter()
+            .map(|(id, path)| (id.to_string(), request(path)))
+            .collect()
+    }
+
+    fn hooks(hooks: &[(&str, &str)]) -> HashMap<String, String> {
+        hooks
+            .iter()
+            .map(|(name, hook)| (name.to_string(), hook.to_string()))
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn sorted(deps: &HashMap<String, HashSet<String>>, id: &str) -> Vec<String> {
+        let mut sorted: Vec<String> = deps[id].iter().cloned().collect();
+        sorted.sort();
+        sorted
+    }
+
+    #[test]
+    fn find_cycle_none() {
+        let requests = requests(&[("login", "/login"), ("me", "/me?t=$token")]);
+        let hooks = hooks(&[("token", "$req.login 10s")]);
+
+        assert_eq!(find_cycle("me", &requests, &hooks), None);
+        assert_eq!(find_cycle("login", &requests, &hooks), None);
+    }
+
+    #[test]
+    fn find_cycle_into_itself() {
+        let requests = requests(&[("me", "/me?t=$me_token")]);
+        let hooks = hooks(&[("me_token", "$req.me")]);
+
+        assert_eq!(
+            find_cycle("me", &requests, &hooks),
+            Some(ids(&["me", "me"]))
+        );
+    }
+
+    #[test]
+    fn find_cycle_through_other_requests() {
+        let requests = requests(&[("start", "/start?x=$a"), ("a", "/a?x=$b"), ("b", "/b?x=$a")]);
+        let hooks = hooks(&[("a", "$req.a"), ("b", "$req.b")]);
+
+        // the requests leading into the cycle aren't part of it
+        assert_eq!(
+            find_cycle("start", &requests, &hooks),
+            Some(ids(&["a", "b", "a"]))
+        );
+    }
+
+    #[test]
+    fn dependencies_follow_hooks() {
+        let requests = requests(&[
+            ("login", "/login"),
+            ("profile", "/profile?t=$token"),
+            ("orders", "/orders?p=$profile_id"),
+            ("other", "/other"),
+        ]);
+        let hooks = hooks(&[("token", "$req.login 10s"), ("profile_id", "$req.profile")]);
+
+        let deps = dependencies(
+            &requests,
+            &hooks,
+            &ids(&["orders", "profile", "login", "other"]),
+        );
+
+        // through other hooked requests too
+        assert_eq!(sorted(&deps, "orders"), ids(&["login", "profile"]));
+        assert_eq!(sorted(&deps, "profile"), ids(&["login"]));
+        assert!(sorted(&deps, "login").is_empty());
+        assert!(sorted(&deps, "other").is_empty());
+    }
+
+    #[test]
+    fn dependencies_only_within_the_run() {
+        let requests = requests(&[("login", "/login"), ("me", "/me?t=$token")]);
+        let hooks = hooks(&[("token", "$req.login")]);
+
+        let deps = dependencies(&requests, &hooks, &ids(&["me"]));
+
+        assert!(sorted(&deps, "me").is_empty());
+    }
+
+    #[test]
+    fn dependencies_follow_script_vars() {
+        let mut requests = requests(&[
+            ("list", "/items"),
+            ("get", "/items/$id"),
+            ("other", "/other"),
+        ]);
+        requests
+            .get_mut("list")
+            .unwrap()
+            .set_post_script("vars.id = \"picked\";".to_string());
+
+        let deps = dependencies(&requests, &HashMap::new(), &ids(&["list", "get", "other"]));
+
+        assert_eq!(sorted(&deps, "get"), ids(&["list"]));
+        assert!(sorted(&deps, "list").is_empty());
+        assert!(sorted(&deps, "other").is_empty());
+
+        // like with `-j 1`, only requests before it set what it uses
+        let deps = dependencies(&requests, &HashMap::new(), &ids(&["get", "list"]));
+
+        assert!(sorted(&deps, "get").is_empty());
+    }
+}
@@ -1,26 +1,81 @@
 use async_recursion::async_recursion;
 use colored::*;
+use cookie_store::RawCookie;
 use core::panic;
 use mime_guess::from_path;
 use regex::Regex;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION, SET_COOKIE,
+};
 use reqwest::multipart::{self, Part};
-use reqwest::{Client, Url};
+use reqwest::{Client, Method, StatusCode, Url};
 use serde_json::to_string_pretty;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{env, fs};
+use tokio::sync::OnceCell;
+use tokio::task::JoinSet;
 
+use crate::auth::Auth;
+use crate::binary;
 use crate::cache::Cache;
+use crate::cookies::CookieJar;
+use crate::dynamic;
+use crate::graph;
+use crate::oauth::{self, OAuth2};
+use crate::output::{self, OutputFormat, Rendered};
+use crate::policy::{CachePolicy, RedirectPolicy, RetryPolicy, TimeoutPolicy};
+use crate::prompt::Prompter;
+use crate::proxy::ProxyConfig;
 use crate::request::Request;
+use crate::response::{Redirect, Response};
+use crate::script::{self, ScriptRequest};
+use crate::secrets::{self, Secrets};
+use crate::tls::TlsConfig;
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+/// `$oauth.` fields that are masked, the rest (`token_type`, `expires_in`,
+/// `scope`) are fine to print.
+const OAUTH_SECRETS: [&str; 3] = ["access_token", "refresh_token", "id_token"];
 
 pub struct LazyReq {
     variables: HashMap<String, String>,
     hooks: HashMap<String, String>,
     requests: HashMap<String, Request>,
+    order: Vec<String>,
+    oauth: OAuth2,
+    tls: TlsConfig,
+    proxy: ProxyConfig,
+    timeout: TimeoutPolicy,
+    retry: RetryPolicy,
+    redirect: RedirectPolicy,
+    forced_redirect: Option<RedirectPolicy>,
+    cookies: CookieJar,
+    cookie_mode: String,
+    clients: Mutex<HashMap<Option<Duration>, Client>>,
+    hook_results: Mutex<HashMap<String, Arc<OnceCell<Response>>>>,
+    oauth_token: OnceCell<Value>,
+    secret_names: HashSet<String>,
+    secrets: Secrets,
+    variable_values: Mutex<HashMap<String, String>>,
+    /// Set by `PRE:`/`POST:` scripts through `vars`, they win over the file.
+    script_vars: Mutex<HashMap<String, String>>,
+    verbose: bool,
+    output: OutputFormat,
+    output_file: Option<String>,
+    remote_name: bool,
+    prompter: Prompter,
+    dotenv: HashMap<String, String>,
     filename: String,
+    environment: String,
 }
 
 impl LazyReq {
@@ -29,63 +84,429 @@ impl LazyReq {
             variables: HashMap::new(),
             hooks: HashMap::new(),
             requests: HashMap::new(),
+            order: Vec::new(),
+            oauth: OAuth2::default(),
+            tls: TlsConfig::default(),
+            proxy: ProxyConfig::default(),
+            timeout: TimeoutPolicy::default(),
+            retry: RetryPolicy::default(),
+            redirect: RedirectPolicy::default(),
+            forced_redirect: None,
+            cookies: CookieJar::new(),
+            cookie_mode: "on".to_string(),
+            clients: Mutex::new(HashMap::new()),
+            hook_results: Mutex::new(HashMap::new()),
+            oauth_token: OnceCell::new(),
+            secret_names: HashSet::new(),
+            secrets: Secrets::new(),
+            variable_values: Mutex::new(HashMap::new()),
+            script_vars: Mutex::new(HashMap::new()),
+            verbose: false,
+            output: OutputFormat::Text,
+            output_file: None,
+            remote_name: false,
+            prompter: Prompter::new(),
+            dotenv: HashMap::new(),
             filename: "".to_string(),
+            environment: "".to_string(),
+        }
+    }
+
+    /// Overrides the TLS settings from the file with the given ones.
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.clients.get_mut().unwrap().clear();
+        self.tls = tls.or(self.tls.clone());
+        if let Some(password) = &self.tls.cert_password {
+            self.secrets.add(password);
         }
     }
 
+    /// Overrides the redirect policy of every request in the file.
+    pub fn set_redirect(&mut self, redirect: RedirectPolicy) {
+        self.forced_redirect = Some(redirect);
+    }
+
+    /// Overrides the proxy settings from the file with the given ones.
+    pub fn set_proxy(&mut self, proxy: ProxyConfig) {
+        self.clients.get_mut().unwrap().clear();
+        self.proxy = proxy.or(self.proxy.clone());
+        if let Some(url) = &self.proxy.url {
+            self.secrets.add_url_password(url);
+        }
+    }
+
+    /// Shows the dynamic values (e.g. `$uuid`) each request was sent with.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    pub fn set_output(&mut self, output: OutputFormat) {
+        self.output = output;
+    }
+
+    /// Saves response bodies to `path` instead of printing them.
+    pub fn set_output_file(&mut self, path: Option<String>) {
+        self.output_file = path;
+    }
+
+    /// Saves response bodies under the name the server suggests.
+    pub fn set_remote_name(&mut self, remote_name: bool) {
+        self.remote_name = remote_name;
+    }
+
+    /// Disables prompting, missing values fail right away instead.
+    pub fn set_input(&mut self, enabled: bool) {
+        self.prompter.enabled = enabled;
+    }
+
+    /// Prints secrets as they are instead of masking them.
+    pub fn set_show_secrets(&mut self, show: bool) {
+        self.secrets.show = show;
+    }
+
+    /// Masks every secret value seen so far in the text.
+    pub fn mask(&self, text: &str) -> String {
+        self.secrets.mask(text)
+    }
+
+    /// Selects which environment specific sections (e.g. `VARS staging`) are
+    /// loaded, must be called before `from_file`.
+    pub fn set_environment(&mut self, environment: String) {
+        self.environment = environment;
+    }
+
+    fn client(&self) -> Result<Client, Box<dyn Error>> {
+        self.client_with_timeout(&self.timeout)
+    }
+
+    /// Returns the client shared by the whole run, so hooks and requests reuse
+    /// pooled connections. The connect timeout can only be set on the client,
+    /// so each distinct one gets its own.
+    fn client_with_timeout(&self, timeout: &TimeoutPolicy) -> Result<Client, Box<dyn Error>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&timeout.connect) {
+            return Ok(client.clone());
+        }
+
+        let mut builder = self.tls.apply(Client::builder())?;
+        if let Some(connect) = timeout.connect {
+            builder = builder.connect_timeout(connect);
+        }
+        builder = builder
+            .redirect(reqwest::redirect::Policy::none())
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_keepalive(TCP_KEEPALIVE);
+
+        let client = self.proxy.apply(builder)?.build()?;
+        clients.insert(timeout.connect, client.clone());
+        Ok(client)
+    }
+
     pub async fn do_request(&self, id: String) {
+        if let Some(cycle) = graph::find_cycle(&id, &self.requests, &self.hooks) {
+            self.fail(&id, &format!("Hook cycle detected: {}", cycle.join(" -> ")));
+        }
+
         match self.requests.get(&id) {
-            Some(req) => {
-                print!(
-                    "{}{}{}",
-                    "[".bold().green(),
-                    req.method.clone().bold().green(),
-                    "]".bold().green()
-                );
-                let (status, url, result) = self.execute(req).await.unwrap();
-                print!(" {}\n", url.bold().green());
-
-                println!("{} {}", "Status:".bold().green(), status.bold().green());
-                let pretty_json: Value =
-                    serde_json::from_str(&result.as_str()).unwrap_or(Value::Null);
-                if !pretty_json.is_null() {
-                    println!("{}", to_string_pretty(&pretty_json).unwrap());
-                } else {
-                    println!("{}", result.bold().green());
+            Some(req) => match self
+                .execute(req)
+                .await
+                .and_then(|response| self.finish(&id, req, &response))
+            {
+                Ok(rendered) => output::print(self.output, &rendered, 0, 1),
+                Err(e) => self.fail(&id, &e.to_string()),
+            },
+            None => self.fail(&id, "Request not found"),
+        }
+    }
+
+    /// Runs several requests, those that don't depend on each other through
+    /// hooks run concurrently (at most `jobs` at a time). Results are printed
+    /// in the order the requests were given.
+    pub async fn do_requests(self: Arc<Self>, ids: Vec<String>, jobs: usize) {
+        for id in &ids {
+            if !self.requests.contains_key(id) {
+                self.fail(id, "Request not found");
+            }
+            if let Some(cycle) = graph::find_cycle(id, &self.requests, &self.hooks) {
+                self.fail(id, &format!("Hook cycle detected: {}", cycle.join(" -> ")));
+            }
+        }
+
+        let mut pending = graph::dependencies(&self.requests, &self.hooks, &ids);
+        let mut running = JoinSet::new();
+        let mut tasks = HashMap::new();
+        let mut outputs: HashMap<String, Rendered> = HashMap::new();
+        let mut printed = 0;
+        let mut failed = false;
+
+        while printed < ids.len() {
+            let ready: Vec<String> = ids
+                .iter()
+                .filter(|id| pending.get(*id).is_some_and(|deps| deps.is_empty()))
+                .take(jobs.max(1).saturating_sub(running.len()))
+                .cloned()
+                .collect();
+
+            for id in ready {
+                pending.remove(&id);
+                let lazyreq = self.clone();
+                let task_id = id.clone();
+                let task = running.spawn(async move {
+                    let req = lazyreq.requests.get(&task_id).unwrap();
+                    let rendered = lazyreq.execute(req).await.and_then(|response| {
+                        lazyreq.remember_hook_result(&task_id, &response);
+                        lazyreq.finish(&task_id, req, &response)
+                    });
+                    match rendered {
+                        Ok(rendered) => (rendered, true),
+                        Err(e) => (lazyreq.render_failure(&task_id, &e.to_string()), false),
+                    }
+                });
+                tasks.insert(task.id(), id);
+            }
+
+            // a panicking request (e.g. a missing variable) only fails itself
+            let (id, output) = match running.join_next_with_id().await {
+                Some(Ok((task, (output, ok)))) => {
+                    failed |= !ok;
+                    (tasks.remove(&task).unwrap(), output)
                 }
+                Some(Err(e)) => {
+                    failed = true;
+                    let id = tasks.remove(&e.id()).unwrap();
+                    let output = self.render_failure(&id, &e.to_string());
+                    (id, output)
+                }
+                None => panic!("dependency cycle between requests: {:?}", pending.keys()),
+            };
+            for deps in pending.values_mut() {
+                deps.remove(&id);
             }
-            None => {
-                println!("Request not found");
+            outputs.insert(id, output);
+
+            while printed < ids.len() {
+                match outputs.remove(&ids[printed]) {
+                    Some(output) => output::print(self.output, &output, printed, ids.len()),
+                    None => break,
+                }
+                printed += 1;
             }
         }
+
+        if failed {
+            process::exit(1);
+        }
     }
 
-    pub async fn export_curl(&self, id: String) {
-        match self.requests.get(&id) {
-            Some(req) => {
-                let curl_command = self.generate_curl_command(req).await;
-                println!("{}", curl_command);
+    /// Request ids in the order they're declared in the file.
+    pub fn request_ids(&self) -> Vec<String> {
+        self.order.clone()
+    }
+
+    /// Saves the body if `-o`/`-O` asked for it and formats a finished
+    /// request for the chosen `--output`.
+    fn finish(
+        &self,
+        id: &str,
+        req: &Request,
+        response: &Response,
+    ) -> Result<Rendered, Box<dyn Error>> {
+        let saved = self
+            .save_body(id, response)
+            .map_err(|e| format!("Failed to save body: {}", e))?;
+        let saved = saved.as_deref();
+
+        let rendered = match self.output {
+            OutputFormat::Text => {
+                Rendered::stdout(self.mask(&self.format_response(req, response, saved)))
+            }
+            OutputFormat::Raw => {
+                let mut metadata = self.format_request_line(req, response);
+                metadata.push_str(&format!(
+                    "{} {}\n",
+                    "Status:".bold().green(),
+                    response.status.to_string().bold().green()
+                ));
+                if let Some(path) = saved {
+                    metadata.push_str(&format_saved(path, response.bytes.len()));
+                }
+
+                let stdout = match (saved, binary::is_binary(&response.headers, &response.bytes)) {
+                    (Some(_), _) => Vec::new(),
+                    // there's nothing to mask in bytes that aren't text
+                    (None, true) => response.bytes.clone(),
+                    (None, false) => self.mask(&response.body).into_bytes(),
+                };
+                Rendered {
+                    stdout,
+                    stderr: self.mask(&metadata).into_bytes(),
+                }
+            }
+            OutputFormat::Json => {
+                let json = output::response_json(id, response, saved, &self.secrets);
+                Rendered::stdout(self.mask(&to_string_pretty(&json).unwrap()) + "\n")
             }
-            None => {
-                println!("Request not found");
+            OutputFormat::Ndjson => {
+                let json = output::response_json(id, response, saved, &self.secrets);
+                Rendered::stdout(self.mask(&json.to_string()) + "\n")
             }
+        };
+
+        Ok(rendered)
+    }
+
+    /// Writes the body as it came to `-o <file>`, or with `-O` to the file
+    /// name the server suggests (or the last part of the url).
+    fn save_body(&self, id: &str, response: &Response) -> Result<Option<String>, Box<dyn Error>> {
+        let path = match (&self.output_file, self.remote_name) {
+            (Some(path), _) => path.clone(),
+            (None, true) => binary::disposition_filename(&response.headers)
+                .or_else(|| {
+                    Url::parse(&response.url)
+                        .ok()?
+                        .path_segments()?
+                        .next_back()
+                        .and_then(binary::safe_filename)
+                })
+                .unwrap_or(id.to_string()),
+            (None, false) => return Ok(None),
+        };
+
+        // like curl's `-J`, a name picked by the server never replaces a file
+        let mut file = match self.output_file {
+            Some(_) => File::create(&path)?,
+            None => OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(|e| format!("{}: {}", path, e))?,
+        };
+        file.write_all(&response.bytes)?;
+        Ok(Some(path))
+    }
+
+    /// Prints a failure that stops the whole run.
+    pub fn fail(&self, id: &str, error: &str) -> ! {
+        output::print(self.output, &self.render_failure(id, error), 0, 1);
+        process::exit(1);
+    }
+
+    fn render_failure(&self, id: &str, error: &str) -> Rendered {
+        let error = self.mask(error);
+        match self.output {
+            OutputFormat::Text => Rendered::stdout(format_failure(id, error)),
+            OutputFormat::Raw => Rendered::stderr(format_failure(id, error)),
+            OutputFormat::Json => Rendered::stdout(
+                to_string_pretty(&output::failure_json(id, &error)).unwrap() + "\n",
+            ),
+            OutputFormat::Ndjson => {
+                Rendered::stdout(output::failure_json(id, &error).to_string() + "\n")
+            }
+        }
+    }
+
+    /// The method and url, followed by the generated values and redirects.
+    fn format_request_line(&self, req: &Request, response: &Response) -> String {
+        let mut output = String::new();
+
+        output.push_str(&format!(
+            "{}{}{} {}\n",
+            "[".bold().green(),
+            req.method.clone().bold().green(),
+            "]".bold().green(),
+            response.url.bold().green()
+        ));
+
+        if self.verbose {
+            for (expression, value) in &response.generated {
+                output.push_str(&format!("  {} = {}\n", expression.dimmed(), value.dimmed()));
+            }
+        }
+
+        for redirect in &response.redirects {
+            output.push_str(&format!(
+                "  {} {} {}\n",
+                redirect.status.to_string().yellow(),
+                "->".yellow(),
+                redirect.location.yellow()
+            ));
+        }
+
+        output
+    }
+
+    fn format_response(&self, req: &Request, response: &Response, saved: Option<&str>) -> String {
+        let mut output = self.format_request_line(req, response);
+
+        output.push_str(&format!(
+            "{} {}\n",
+            "Status:".bold().green(),
+            response.status.to_string().bold().green()
+        ));
+        if let Some(location) = response.headers.get(LOCATION) {
+            output.push_str(&format!(
+                "{} {}\n",
+                "Location:".bold().green(),
+                location.to_str().unwrap_or("").bold().green()
+            ));
+        }
+        if let Some(path) = saved {
+            output.push_str(&format_saved(path, response.bytes.len()));
+            return output;
+        }
+        // binary bodies would only fill the terminal with garbage
+        if binary::is_binary(&response.headers, &response.bytes) {
+            output.push_str(&binary::preview(&response.headers, &response.bytes));
+            return output;
+        }
+
+        let pretty_json: Value =
+            serde_json::from_str(response.body.as_str()).unwrap_or(Value::Null);
+        if !pretty_json.is_null() {
+            output.push_str(&format!("{}\n", to_string_pretty(&pretty_json).unwrap()));
+        } else {
+            output.push_str(&format!("{}\n", response.body.bold().green()));
+        }
+
+        output
+    }
+
+    pub async fn export_curl(&self, id: String) {
+        match self.requests.get(&id) {
+            Some(req) => match self.generate_curl_command(req).await {
+                Ok(curl_command) => println!("{}", curl_command),
+                Err(e) => self.fail(&id, &e.to_string()),
+            },
+            None => self.fail(&id, "Request not found"),
         }
     }
 
-    async fn generate_curl_command(&self, req: &Request) -> String {
-        let url = self.handle_variables_and_hooks(req.path.clone()).await;
-        
+    async fn generate_curl_command(&self, req: &Request) -> Result<String, Box<dyn Error>> {
+        let mut generated = Vec::new();
+        let url = self
+            .handle_variables_and_hooks(req.path.clone(), &mut generated)
+            .await?;
+
         let mut headers = req.headers.clone();
         for (key, value) in &req.headers {
-            let normalized = self.handle_variables_and_hooks(value.clone()).await;
-            headers.insert(key.clone(), normalized.clone());
+            let normalized = self
+                .handle_variables_and_hooks(value.clone(), &mut generated)
+                .await?;
+            headers.insert(key.clone(), normalized);
         }
 
         let mut curl_parts = vec![format!("curl -X {}", req.method.to_uppercase())];
-        
+        curl_parts.extend(self.tls.curl_args());
+        curl_parts.extend(self.proxy.curl_args());
+
         // Add headers
         for (key, value) in &headers {
-            curl_parts.push(format!("-H \"{}: {}\"", key, value));
+            curl_parts.push(format!(
+                "-H \"{}: {}\"",
+                key,
+                self.secrets.mask_header(key, value)
+            ));
         }
 
         // Handle multipart data
@@ -104,123 +525,512 @@ impl LazyReq {
             }
         } else if !req.body.is_empty() {
             // Add body data
-            curl_parts.push(format!("-d '{}'", req.body));
+            let body = dynamic::resolve(&req.body, &mut generated);
+            curl_parts.push(format!("-d '{}'", body));
         }
 
         // Add URL
         curl_parts.push(format!("\"{}\"", url));
 
-        curl_parts.join(" \\\n  ")
+        Ok(self.mask(&curl_parts.join(" \\\n  ")))
     }
 
     #[async_recursion]
-    pub async fn handle_macro(&self, macr: String, splits: Vec<&str>) -> (String, String) {
+    pub async fn handle_macro(
+        &self,
+        macr: String,
+        splits: Vec<&str>,
+    ) -> Result<Option<Response>, Box<dyn Error>> {
         if macr.starts_with("$req.") {
             let macro_parsed = &macr.replace("$req.", "");
+            if let Some(cycle) = graph::find_cycle(macro_parsed, &self.requests, &self.hooks) {
+                return Err(format!("Hook cycle detected: {}", cycle.join(" -> ")).into());
+            }
+
+            // a hook runs at most once per run, however many times it's referenced
+            let result = self
+                .hook_memo(macro_parsed)
+                .get_or_try_init(|| self.run_hook(macro_parsed, &splits[1..]))
+                .await?
+                .clone();
+
+            return Ok(Some(result));
+        }
 
-            let mut cacher: Option<Cache> = None;
-            if splits.len() > 1 {
-                cacher = Some(Cache::new(&self.filename, macro_parsed));
-                let has = cacher.as_mut().unwrap().get();
-                if has.is_some() {
-                    return (macro_parsed.clone(), has.unwrap());
+        Ok(None)
+    }
+
+    fn hook_memo(&self, id: &str) -> Arc<OnceCell<Response>> {
+        self.hook_results
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Lets hooks on a request that already ran use its response instead of
+    /// running it again.
+    fn remember_hook_result(&self, id: &str, response: &Response) {
+        // already set when a hook got to it first
+        let _ = self
+            .hook_memo(id)
+            .set(with_redirect_cookies(response.clone()));
+    }
+
+    async fn run_hook(&self, id: &str, options: &[&str]) -> Result<Response, Box<dyn Error>> {
+        let policy = CachePolicy::parse(options);
+
+        let mut cacher: Option<Cache> = None;
+        if policy.is_some() {
+            let definition = self.resolve_variables(self.requests.get(id).unwrap().definition())?;
+            cacher = Some(Cache::new(
+                &self.filename,
+                &self.environment,
+                id,
+                definition.as_str(),
+            ));
+            if let Some(has) = cacher.as_mut().unwrap().get_response() {
+                self.restore_cookies(self.requests.get(id).unwrap(), &has)?;
+                return Ok(has);
+            }
+        }
+
+        let req = self.requests.get(id).unwrap();
+        let response = self
+            .execute(req)
+            .await
+            .map_err(|e| format!("Hook {} failed: {}", id, e))?;
+        let response = with_redirect_cookies(response);
+
+        if let (Some(policy), Some(cacher)) = (policy, cacher.as_mut()) {
+            // a failed login shouldn't stick around for the whole TTL
+            let cacheable = policy.should_cache(response.status.as_u16());
+            let ttl = policy.ttl_for(&response.headers, &response.body);
+
+            if let (true, Some(ttl)) = (cacheable, ttl) {
+                cacher.set_response(
+                    response.status.to_string(),
+                    &response.headers,
+                    response.body.clone(),
+                    ttl.as_secs(),
+                );
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Puts the cookies of a cached hook response back in the jar, like
+    /// they would be if the hook had run, so the session it started goes on.
+    fn restore_cookies(&self, req: &Request, response: &Response) -> Result<(), Box<dyn Error>> {
+        if !req.cookies || self.cookie_mode == "off" {
+            return Ok(());
+        }
+
+        let path = self.resolve_sources(&self.resolve_variables(req.path.clone())?)?;
+        // a URL still made of hooks can't be known without running them
+        if let Ok(url) = Url::parse(&dynamic::unescape(&path)) {
+            self.cookies.store(&url, &response.headers);
+        }
+        Ok(())
+    }
+
+    /// Picks a value out of a hook's response: `status`, `headers.<name>`,
+    /// `cookies.<name>`, the raw `body`, or a JSON path into the body like
+    /// `token` or `body.data.token`.
+    fn hook_value(&self, response: &Response, path: &str) -> Option<String> {
+        let (field, rest) = path.split_once(".").unwrap_or((path, ""));
+        match (field, rest) {
+            ("status", "") => Some(response.status.as_u16().to_string()),
+            ("headers", name) if !name.is_empty() => {
+                let values = response
+                    .headers
+                    .get_all(name)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .collect::<Vec<&str>>();
+                match values.is_empty() {
+                    true => None,
+                    false => Some(values.join(", ")),
                 }
             }
+            ("cookies", name) if !name.is_empty() => response
+                .headers
+                .get_all(SET_COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .filter_map(|value| RawCookie::parse(value).ok())
+                .rfind(|cookie| cookie.name() == name)
+                .map(|cookie| cookie.value().to_string()),
+            ("body", "") => Some(response.body.clone()),
+            ("body", path) => json_value(&response.body, path),
+            _ => json_value(&response.body, path),
+        }
+    }
+
+    /// Replaces plain variables only, hooks and commands are left as they are.
+    fn resolve_variables(&self, data: String) -> Result<String, Box<dyn Error>> {
+        let re = Regex::new(r"\$(\w+)").unwrap();
+
+        let mut resolved = String::new();
+        let mut last = 0;
+        for captures in re.captures_iter(&data) {
+            let found = captures.get(0).unwrap();
+            resolved.push_str(&data[last..found.start()]);
+            last = found.end();
 
-            let req = self.requests.get(macro_parsed).unwrap();
-            let (_, _, result) = self.execute(req).await.unwrap();
-            if splits.len() > 1 && cacher.is_some() {
-                cacher
-                    .as_mut()
-                    .unwrap()
-                    .set(result.clone(), splits[1].parse::<u64>().unwrap());
+            match self.variables.get(&captures[1]) {
+                Some(value) if value.starts_with("$cmd.") => resolved.push_str(value),
+                Some(value) => {
+                    let secret = self.secret_names.contains(&captures[1]);
+                    resolved.push_str(&dynamic::unescape(&self.resolve_sources_as(value, secret)?));
+                }
+                None => resolved.push_str(found.as_str()),
             }
+        }
+        resolved.push_str(&data[last..]);
 
-            return (macro_parsed.clone(), result);
+        Ok(resolved)
+    }
+
+    /// The token is only fetched once per run, however many `$oauth.` values
+    /// and requests use it.
+    async fn oauth_token(&self) -> Result<&Value, Box<dyn Error>> {
+        self.oauth_token
+            .get_or_try_init(|| self.handle_oauth())
+            .await
+    }
+
+    async fn handle_oauth(&self) -> Result<Value, Box<dyn Error>> {
+        let mut resolved = self.oauth.clone();
+        let mut generated = Vec::new();
+        for field in [
+            &mut resolved.token_url,
+            &mut resolved.client_id,
+            &mut resolved.client_secret,
+            &mut resolved.username,
+            &mut resolved.password,
+        ] {
+            *field = self
+                .handle_variables_and_hooks(field.clone(), &mut generated)
+                .await?;
         }
+        self.secrets.add(resolved.client_secret.as_str());
+        self.secrets.add(resolved.password.as_str());
+
+        let client = self
+            .client()
+            .map_err(|e| format!("Failed to build http client: {}", e))?;
 
-        return (String::new(), String::new());
+        oauth::get_token(
+            &client,
+            &resolved,
+            &self.timeout,
+            &self.filename,
+            &self.environment,
+        )
+        .await
+        .map_err(|e| format!("Failed to get oauth2 token: {}", e).into())
     }
 
+    /// Puts variables, hooks, `$oauth.` and script values into a template
+    /// from the .lreq file, then resolves `$env.`, `$file.`, `$prompt.` and
+    /// dynamic values. Those are only looked for in the template itself,
+    /// never in what was put into it.
     #[async_recursion]
-    async fn handle_variables_and_hooks(&self, data: String) -> String {
-        let pattern = r"\$[\w.]+";
+    async fn handle_variables_and_hooks(
+        &self,
+        data: String,
+        generated: &mut Vec<(String, String)>,
+    ) -> Result<String, Box<dyn Error>> {
+        // `-` only after a dot, for paths like `$login.headers.x-auth-token`
+        let pattern = r"\$\w+(?:\.[\w-]+)*";
         let re = Regex::new(pattern).unwrap();
 
-        let mut url = data.clone();
+        let mut url = String::new();
+        let mut last = 0;
         for i in re.find_iter(&data) {
-            if i.as_str().starts_with("$$") {
+            url.push_str(&data[last..i.start()]);
+            last = i.end();
+
+            let replace_value = i.as_str();
+            let item = replace_value[1..].split(".").next().unwrap().to_string();
+
+            // resolved after everything else, see `resolve_sources`
+            // generated per request, see `dynamic::resolve`
+            if item == "env"
+                || item == "file"
+                || item == "prompt"
+                || dynamic::is_builtin(item.as_str())
+            {
+                url.push_str(replace_value);
                 continue;
             }
 
-            let replace_value = i.clone().as_str();
-            let mut item: String = i.as_str().chars().skip(1).collect();
+            if item == "oauth" && self.oauth.is_configured() {
+                let path = replace_value.replace("$oauth.", "");
+                let value = match value_at(self.oauth_token().await?, &path) {
+                    Some(value) => value,
+                    None => return Err(format!("OAuth2 field {} not found", replace_value).into()),
+                };
 
-            item = item.split(".").collect::<Vec<&str>>()[0].to_string();
+                if OAUTH_SECRETS.contains(&path.as_str()) {
+                    self.secrets.add(&value);
+                }
+                url.push_str(&dynamic::escape(&value));
+                continue;
+            }
 
-            let is_hook = self.hooks.get(item.as_str().trim());
-            if is_hook.is_some() {
-                let hook = is_hook.unwrap().to_string();
-                let parts: Vec<&str> = hook.split(" ").collect::<Vec<&str>>();
+            let script_value = self.script_vars.lock().unwrap().get(&item).cloned();
+            if let Some(value) = script_value {
+                url.push_str(&dynamic::escape(&value));
+                continue;
+            }
 
-                let (macro_name, macro_result) =
-                    self.handle_macro(parts[0].to_string(), parts).await;
-                let mut parsed: Value = serde_json::from_str(&macro_result.as_str()).unwrap();
+            if let Some(hook) = self.hooks.get(item.as_str()) {
+                let parts: Vec<&str> = hook.split_whitespace().collect::<Vec<&str>>();
 
-                let macro_name_parsed = "$".to_string() + macro_name.as_str() + ".";
-                let replaced = replace_value.replace(macro_name_parsed.as_str(), "");
-                let parts = replaced.split(".").collect::<Vec<&str>>();
+                let response = match self.handle_macro(parts[0].to_string(), parts).await? {
+                    Some(response) => response,
+                    None => return Err(format!("Invalid hook {}", hook).into()),
+                };
 
-                for part in parts.iter() {
-                    if parsed.get(part).is_none() {
-                        panic!("Macro {} not found", replace_value);
-                    }
+                let path = replace_value
+                    .strip_prefix(format!("${}.", item).as_str())
+                    .unwrap_or("body");
+                let value = match self.hook_value(&response, path) {
+                    Some(value) => value,
+                    None => return Err(format!("Macro {} not found", replace_value).into()),
+                };
 
-                    parsed = parsed.get(part).unwrap().clone();
+                if self.secret_names.contains(item.as_str()) {
+                    self.secrets.add(value.as_str());
+                }
+                url.push_str(&dynamic::escape(&value));
+            } else if self.variables.contains_key(item.as_str()) {
+                // already escaped where it needs to be
+                url.push_str(&self.variable_value(item.as_str())?);
+            } else {
+                match self.prompter.ask(item.as_str(), None, false) {
+                    Some(answer) => url.push_str(&dynamic::escape(&answer)),
+                    None => return Err(format!("Variable or hook not found: {}", item).into()),
                 }
-
-                url = url.replace(&replace_value, &parsed.as_str().unwrap())
             }
+        }
+        url.push_str(&data[last..]);
+
+        let url = self.resolve_sources(url.as_str())?;
+        Ok(dynamic::unescape(&dynamic::resolve(&url, generated)))
+    }
+
+    /// Resolves a variable the first time it's used, a `$cmd.` value runs its
+    /// command only then, and only once per run.
+    fn variable_value(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        if let Some(value) = self.variable_values.lock().unwrap().get(name) {
+            return Ok(value.clone());
+        }
+
+        let raw = self.variables.get(name).unwrap();
+        let value = match raw.strip_prefix("$cmd.") {
+            Some(command) => dynamic::escape(&self.run_command(command)?),
+            None => self.resolve_sources_as(raw, self.secret_names.contains(name))?,
+        };
+        if self.secret_names.contains(name) {
+            self.secrets.add(dynamic::unescape(&value).as_str());
+        }
 
-            let is_variable = self.variables.get(item.as_str());
-            if is_variable.is_some() {
-                url = url.replace(&replace_value, &is_variable.unwrap().to_string());
+        self.variable_values
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    fn resolve_sources(&self, text: &str) -> Result<String, Box<dyn Error>> {
+        self.resolve_sources_as(text, false)
+    }
+
+    /// Replaces `$env.NAME`, `$env.NAME:-fallback`, `$file.path` and
+    /// `$prompt.name` (or `$prompt.name:-default`) anywhere in the text,
+    /// what's put in is escaped (see `dynamic::escape`).
+    /// Environment variables fall back to the `.env` file next to the .lreq
+    /// file, paths are relative to it too. Prompts for a secret variable
+    /// don't echo the input.
+    fn resolve_sources_as(&self, text: &str, secret: bool) -> Result<String, Box<dyn Error>> {
+        let env_re = Regex::new(r#"\$env\.(\w+)(:-[^\s"',;&)]*)?"#).unwrap();
+        let file_re = Regex::new(r"\$file\.([\w./~-]+)").unwrap();
+        let prompt_re = Regex::new(r#"\$prompt\.(\w+)(:-[^\s"',;&)]*)?"#).unwrap();
+
+        // the closures can't return early, the first error is kept for after
+        let mut failure: Option<String> = None;
+
+        let text = prompt_re.replace_all(text, |captures: &regex::Captures| {
+            let name = &captures[1];
+            let default = captures.get(2).map(|default| &default.as_str()[2..]);
+
+            match self.prompter.ask(name, default, secret) {
+                Some(answer) => dynamic::escape(&answer),
+                None => {
+                    failure.get_or_insert(format!("No input available for {}", name));
+                    String::new()
+                }
             }
+        });
 
-            if !is_variable.is_some() && !is_hook.is_some() {
-                panic!(
-                    "Variable or hook not found: {}",
-                    item.to_string().to_string()
-                );
+        let resolved = env_re.replace_all(&text, |captures: &regex::Captures| {
+            let name = &captures[1];
+            let fallback = captures.get(2).map(|fallback| &fallback.as_str()[2..]);
+
+            match (env::var(name).ok(), self.dotenv.get(name), fallback) {
+                (Some(value), _, _) => dynamic::escape(&value),
+                (None, Some(value), _) => dynamic::escape(value),
+                (None, None, Some(fallback)) => fallback.to_string(),
+                (None, None, None) => {
+                    failure.get_or_insert(format!("Environment variable not set: {}", name));
+                    String::new()
+                }
             }
+        });
+
+        let resolved = file_re
+            .replace_all(&resolved, |captures: &regex::Captures| {
+                let path = self.relative_path(&captures[1]);
+                match fs::read_to_string(&path) {
+                    Ok(content) => dynamic::escape(content.trim_end_matches(['\r', '\n'])),
+                    Err(e) => {
+                        failure.get_or_insert(format!("Failed to read {}: {}", path.display(), e));
+                        String::new()
+                    }
+                }
+            })
+            .to_string();
+
+        match failure {
+            Some(e) => Err(e.into()),
+            None => Ok(resolved),
+        }
+    }
+
+    /// Runs a command through the shell from the .lreq file's directory and
+    /// returns its output.
+    fn run_command(&self, command: &str) -> Result<String, Box<dyn Error>> {
+        let mut shell = match cfg!(windows) {
+            true => process::Command::new("cmd"),
+            false => process::Command::new("sh"),
+        };
+        shell
+            .arg(if cfg!(windows) { "/C" } else { "-c" })
+            .arg(command);
+        shell.current_dir(self.relative_path("."));
+
+        let output = shell
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Command {} failed ({}): {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
 
-            // url = url.replace(&item, &self.variables.get(&item).unwrap());
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_string())
+    }
+
+    /// Resolves a path against the directory of the .lreq file, `~` is the
+    /// home directory.
+    fn relative_path(&self, path: &str) -> PathBuf {
+        if let Some(rest) = path.strip_prefix("~/") {
+            return home::home_dir().unwrap_or_default().join(rest);
         }
 
-        return url;
+        let dir = Path::new(&self.filename)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        dir.join(path)
     }
 
     #[async_recursion]
-    async fn execute(&self, req: &Request) -> Result<(String, String, String), Box<dyn Error>> {
-        let url = self.handle_variables_and_hooks(req.path.clone()).await;
+    async fn execute(&self, req: &Request) -> Result<Response, Box<dyn Error>> {
+        let prepared = self.prepare(req).await?;
+
+        let sent = Instant::now();
+        let (response, redirects) = self.send_prepared(&prepared).await?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+
+        let bytes = response.bytes().await?.to_vec();
+        let body = binary::decode_text(&headers, &bytes);
+        let response = Response {
+            method: prepared.outgoing.method.to_string(),
+            status,
+            url: prepared.url.clone(),
+            sent_headers: prepared.outgoing.headers.clone(),
+            headers,
+            body,
+            bytes,
+            redirects,
+            generated: prepared.generated.clone(),
+            elapsed: sent.elapsed(),
+        };
+
+        if !req.post_script.is_empty() {
+            let request = prepared.script_request();
+            script::run_post(&req.post_script, &request, &response, &self.script_vars)?;
+        }
+
+        Ok(response)
+    }
+
+    pub fn request(&self, id: &str) -> Option<&Request> {
+        self.requests.get(id)
+    }
+
+    /// Resolves variables, hooks, auth and multipart files of a request, the
+    /// result can be sent any number of times.
+    #[async_recursion]
+    pub async fn prepare(&self, req: &Request) -> Result<Prepared, Box<dyn Error>> {
+        let mut generated = Vec::new();
+        let url = self
+            .handle_variables_and_hooks(req.path.clone(), &mut generated)
+            .await?;
 
         let mut headers = req.headers.clone();
         for (key, value) in &req.headers {
-            let normalized = self.handle_variables_and_hooks(value.clone()).await;
-            headers.insert(key.clone(), normalized.clone());
+            let normalized = self
+                .handle_variables_and_hooks(value.clone(), &mut generated)
+                .await?;
+            headers.insert(key.clone(), normalized);
         }
 
-        let mut new = Request::new(
-            req.method.clone(),
-            url.clone(),
-            req.body.clone(),
-            req.multipart.clone(),
-        );
+        let body = dynamic::resolve(&req.body, &mut generated);
+        let mut new = Request::new(req.method.clone(), url, body, req.multipart.clone());
 
         if !headers.is_empty() {
             new.set_headers(headers);
         }
 
+        // before signing, so the signature covers what the script changed
+        if !req.pre_script.is_empty() {
+            let mut request = ScriptRequest::from_request(&new);
+            script::run_pre(&req.pre_script, &mut request, &self.script_vars)?;
+            request.apply(&mut new);
+        }
+
+        if !req.auth.is_empty() {
+            let auth = self
+                .handle_variables_and_hooks(req.auth.clone(), &mut generated)
+                .await?;
+            Auth::parse(auth.as_str())?.sign(&mut new)?;
+        }
+
         let http_method = new.format_method();
         let mut http_headers = HeaderMap::new();
         for (key, value) in new.headers.clone() {
@@ -230,95 +1040,329 @@ impl LazyReq {
             );
         }
 
-        let mut multipart: Option<multipart::Form> = None;
+        let timeout = req
+            .timeout
+            .as_ref()
+            .unwrap_or(&self.timeout)
+            .or(&self.timeout);
+        let retry = req.retry.as_ref().unwrap_or(&self.retry);
+        let redirect = self
+            .forced_redirect
+            .as_ref()
+            .or(req.redirect.as_ref())
+            .unwrap_or(&self.redirect);
+
+        let use_cookies = req.cookies && self.cookie_mode != "off";
+
+        let client = self.client_with_timeout(&timeout)?;
+        let mut form_parts: Option<Vec<FormPart>> = None;
 
         let new_multipart = new.multipart.clone();
-        if new_multipart.len() > 0 {
-            let mut m = multipart::Form::new();
+        if !new_multipart.is_empty() {
+            let mut parts = Vec::new();
 
             for part in new_multipart.iter() {
                 if part.content.starts_with("download://") {
                     let path_str = part.content.clone().replace("download://", "");
-                    let bytes = reqwest::get(path_str.clone()).await?.bytes().await?;
+                    let download = Outgoing {
+                        method: Method::GET,
+                        url: path_str.clone(),
+                        headers: HeaderMap::new(),
+                        body: String::new(),
+                        form: None,
+                    };
+                    let (response, _) = self
+                        .send(
+                            &client,
+                            download,
+                            &timeout,
+                            retry,
+                            &RedirectPolicy::default(),
+                            use_cookies,
+                        )
+                        .await?;
+                    let bytes = response.bytes().await?;
                     let file_name = Url::parse(path_str.clone().as_str())
                         .unwrap()
                         .path_segments()
                         .unwrap()
-                        .last()
+                        .next_back()
                         .unwrap_or("file")
                         .to_string();
 
-                    let mime = from_path(&path_str.as_str()).first_or_octet_stream();
+                    let mime = from_path(path_str.as_str()).first_or_octet_stream();
 
-                    let file_part = Part::bytes(bytes.to_vec())
-                        .file_name(file_name.clone())
-                        .mime_str(mime.as_ref())
-                        .ok();
-                    m = m.part(part.name.clone(), file_part.unwrap());
+                    parts.push(FormPart::File {
+                        name: part.name.clone(),
+                        content: bytes.to_vec(),
+                        file_name,
+                        mime: mime.to_string(),
+                    });
                 } else if part.content.starts_with("file://") {
                     let path_str = part.content.clone().replace("file://", "");
                     let path = Path::new(&path_str);
                     let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
                     let content: Vec<u8> = fs::read(path).unwrap();
 
-                    let mime = from_path(&path).first_or_octet_stream();
-
-                    let file_part = Part::bytes(content)
-                        .file_name(file_name.clone())
-                        .mime_str(mime.as_ref())
-                        .ok();
+                    let mime = from_path(path).first_or_octet_stream();
 
-                    m = m.part(part.name.clone(), file_part.unwrap());
+                    parts.push(FormPart::File {
+                        name: part.name.clone(),
+                        content,
+                        file_name,
+                        mime: mime.to_string(),
+                    });
                 } else {
-                    m = m.text(part.name.clone(), part.content.to_string());
+                    parts.push(FormPart::Text {
+                        name: part.name.clone(),
+                        content: dynamic::resolve(&part.content, &mut generated),
+                    });
                 }
             }
 
-            multipart = Some(m);
+            form_parts = Some(parts);
             http_headers.remove("Content-Type");
         }
 
-        let client = Client::new();
-
-        let response = if multipart.is_some() {
-            client
-                .request(http_method, new.path)
-                .headers(http_headers)
-                .multipart(multipart.unwrap())
-                .send()
-                .await?
-        } else {
-            client
-                .request(http_method, new.path)
-                .body(new.body)
-                .headers(http_headers)
-                .send()
-                .await?
+        let outgoing = Outgoing {
+            method: http_method,
+            url: new.path.clone(),
+            headers: http_headers,
+            body: new.body.clone(),
+            form: form_parts,
         };
+        Ok(Prepared {
+            url: new.path,
+            generated,
+            client,
+            outgoing,
+            timeout,
+            retry: retry.clone(),
+            redirect: redirect.clone(),
+            use_cookies,
+        })
+    }
 
-        let status = response.status();
+    pub async fn send_prepared(
+        &self,
+        prepared: &Prepared,
+    ) -> Result<(reqwest::Response, Vec<Redirect>), Box<dyn Error>> {
+        self.send(
+            &prepared.client,
+            prepared.outgoing.clone(),
+            &prepared.timeout,
+            &prepared.retry,
+            &prepared.redirect,
+            prepared.use_cookies,
+        )
+        .await
+    }
+
+    /// Sends the request following redirects by hand, so every hop can be
+    /// reported back.
+    async fn send(
+        &self,
+        client: &Client,
+        mut outgoing: Outgoing,
+        timeout: &TimeoutPolicy,
+        retry: &RetryPolicy,
+        redirect: &RedirectPolicy,
+        use_cookies: bool,
+    ) -> Result<(reqwest::Response, Vec<Redirect>), Box<dyn Error>> {
+        let mut redirects = Vec::new();
 
-        let body = response.text().await?;
-        Ok((status.to_string(), url, body))
+        loop {
+            let current = Url::parse(outgoing.url.as_str())?;
+            let cookie = match use_cookies {
+                true => self.cookies.header(&current),
+                false => None,
+            };
+            // session cookies are as good as a password
+            for pair in cookie.iter().flat_map(|cookie| cookie.split("; ")) {
+                if let Some((_, value)) = pair.split_once("=") {
+                    self.secrets.add(value);
+                }
+            }
+
+            let response = self
+                .send_with_retry(client, &outgoing, cookie, timeout, retry)
+                .await?;
+            let status = response.status();
+            if use_cookies {
+                self.cookies.store(&current, response.headers());
+            }
+
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok());
+            let location = match location {
+                Some(location) if status.is_redirection() => location,
+                _ => return Ok((response, redirects)),
+            };
+
+            if !redirect.is_enabled() || redirects.len() >= redirect.max {
+                return Ok((response, redirects));
+            }
+
+            let next = current.join(location)?;
+            redirects.push(Redirect {
+                status,
+                location: next.to_string(),
+                cookies: response
+                    .headers()
+                    .get_all(SET_COOKIE)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .map(String::from)
+                    .collect(),
+            });
+
+            // same rules as browsers: 301/302/303 turn into a GET without a body
+            let keeps_method = status == StatusCode::TEMPORARY_REDIRECT
+                || status == StatusCode::PERMANENT_REDIRECT;
+            if !keeps_method && outgoing.method != Method::HEAD {
+                outgoing.method = Method::GET;
+                outgoing.body = String::new();
+                outgoing.form = None;
+                outgoing.headers.remove(CONTENT_TYPE);
+                outgoing.headers.remove(CONTENT_LENGTH);
+            }
+
+            // like reqwest, credentials only follow to the same scheme, host and port
+            let same_origin = current.scheme() == next.scheme()
+                && current.host_str() == next.host_str()
+                && current.port_or_known_default() == next.port_or_known_default();
+            if !same_origin {
+                let sensitive: Vec<HeaderName> = outgoing
+                    .headers
+                    .keys()
+                    .filter(|name| secrets::is_sensitive_header(name.as_str()))
+                    .cloned()
+                    .collect();
+                for name in sensitive {
+                    outgoing.headers.remove(name);
+                }
+            }
+
+            outgoing.url = next.to_string();
+        }
+    }
+
+    async fn send_with_retry(
+        &self,
+        client: &Client,
+        outgoing: &Outgoing,
+        cookie: Option<String>,
+        timeout: &TimeoutPolicy,
+        retry: &RetryPolicy,
+    ) -> Result<reqwest::Response, Box<dyn Error>> {
+        let mut headers = outgoing.headers.clone();
+        if let Some(cookie) = cookie {
+            // cookies from the jar go along with the ones set by hand
+            let value = match headers.get(COOKIE).and_then(|c| c.to_str().ok()) {
+                Some(explicit) => format!("{}; {}", explicit, cookie),
+                None => cookie,
+            };
+            headers.insert(COOKIE, HeaderValue::from_str(value.as_str())?);
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+
+            let mut builder = client
+                .request(outgoing.method.clone(), outgoing.url.clone())
+                .headers(headers.clone());
+            builder = match &outgoing.form {
+                Some(parts) => builder.multipart(build_form(parts)),
+                None => builder.body(outgoing.body.clone()),
+            };
+            if let Some(total) = timeout.total {
+                builder = builder.timeout(total);
+            }
+
+            let result = builder.send().await;
+            let retryable = match &result {
+                Ok(response) => retry.should_retry_status(response.status()),
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+
+            if !retryable || attempt > retry.count {
+                return Ok(result?);
+            }
+
+            let delay = match &result {
+                Ok(response) => retry.delay(attempt, Some(response.status()), response.headers()),
+                Err(_) => retry.delay(attempt, None, &HeaderMap::new()),
+            };
+            let reason = match &result {
+                Ok(response) => response.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+            eprintln!(
+                "{} attempt {}/{} failed ({}), retrying in {:.1}s",
+                "[RETRY]".bold().yellow(),
+                attempt,
+                retry.count + 1,
+                self.mask(&reason),
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+        }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     pub fn from_file(&mut self, filename: String) {
         self.filename = filename.clone();
+        self.dotenv = load_dotenv(&self.relative_path(".env"));
         let mut context = "VARS";
         let mut last_id: String = String::new();
         let mut request_body: String = String::new();
+        let mut section_env = String::new();
+        let mut deferred: Vec<(&str, String)> = Vec::new();
+        let mut script: Option<(&str, String)> = None;
         for line in fs::read_to_string(filename).unwrap().lines() {
             let mut line = line.to_string();
+            // script lines are kept as they are until `END`
+            if let Some((kind, source)) = script.as_mut() {
+                if line.trim() != "END" {
+                    source.push_str(&line);
+                    source.push('\n');
+                    continue;
+                }
+                let req = self.requests.get_mut(&last_id).unwrap();
+                match *kind {
+                    "PRE" => req.set_pre_script(source.clone()),
+                    _ => req.set_post_script(source.clone()),
+                }
+                script = None;
+                continue;
+            }
             if line.trim().starts_with("#") {
                 continue;
             }
             if line.starts_with("VARS") {
                 context = "VARS";
+                section_env = section_environment(&line);
             } else if line.starts_with("HOOKS") {
                 context = "HOOKS";
+                section_env = section_environment(&line);
+            } else if line.starts_with("OAUTH2") {
+                context = "OAUTH2";
+                section_env = section_environment(&line);
+            } else if line.starts_with("TLS") {
+                context = "TLS";
+                section_env = section_environment(&line);
+            } else if line.starts_with("PROXY") {
+                context = "PROXY";
+                section_env = section_environment(&line);
+            } else if line.starts_with("DEFAULTS") {
+                context = "DEFAULTS";
+                section_env = section_environment(&line);
             } else if line.starts_with("ID:") {
                 context = "REQUEST";
-                if request_body != "" {
+                if !request_body.is_empty() {
                     let req = self.requests.get_mut(&last_id).unwrap();
                     req.set_body(request_body);
                     request_body = String::new();
@@ -330,27 +1374,14 @@ impl LazyReq {
                     continue;
                 }
 
-                if context == "VARS" {
-                    let parts = line.split("=").collect::<Vec<&str>>();
-                    if parts.len() != 2 {
-                        panic!("invalid variable provided {}", line);
+                if context != "REQUEST" {
+                    if section_env.is_empty() {
+                        self.parse_setting(context, &line);
+                    } else if section_env == self.environment {
+                        // environment specific settings override the defaults
+                        deferred.push((context, line));
                     }
-                    let mut value = parts[1].trim().to_string();
-                    if value.starts_with("$env.") {
-                        value = env::var(value.replace("$env.", "")).unwrap();
-                    }
-
-                    if value.starts_with('"') && value.ends_with('"')
-                        || value.starts_with("'") && value.ends_with("'")
-                    {
-                        value = value.replace('"', "").replace("'", "");
-                    }
-
-                    self.add_variable(parts[0].trim().to_string(), value);
-                }
-                if context == "HOOKS" {
-                    let parts = line.split("=").collect::<Vec<&str>>();
-                    self.add_hook(parts[0].trim().to_string(), parts[1].trim().to_string());
+                    continue;
                 }
 
                 if context == "REQUEST" {
@@ -367,6 +1398,47 @@ impl LazyReq {
                         continue;
                     }
 
+                    if line.starts_with("AUTH:") {
+                        line = line.replace("AUTH:", "");
+                        req.set_auth(line.trim().to_string());
+                        continue;
+                    }
+
+                    if line.starts_with("TIMEOUT:") {
+                        line = line.replace("TIMEOUT:", "");
+                        req.set_timeout(TimeoutPolicy::parse(line.trim()));
+                        continue;
+                    }
+
+                    if line.starts_with("RETRY:") {
+                        line = line.replace("RETRY:", "");
+                        req.set_retry(RetryPolicy::parse(line.trim()));
+                        continue;
+                    }
+
+                    if line.starts_with("REDIRECTS:") {
+                        line = line.replace("REDIRECTS:", "");
+                        req.set_redirect(RedirectPolicy::parse(line.trim()));
+                        continue;
+                    }
+
+                    if line.starts_with("COOKIES:") {
+                        line = line.replace("COOKIES:", "");
+                        req.set_cookies(line.trim() != "off");
+                        continue;
+                    }
+
+                    if line.starts_with("PRE:") || line.starts_with("POST:") {
+                        let (kind, inline) = line.split_once(":").unwrap();
+                        let kind = if kind == "PRE" { "PRE" } else { "POST" };
+                        match (inline.trim().is_empty(), kind) {
+                            (true, _) => script = Some((kind, String::new())),
+                            (false, "PRE") => req.set_pre_script(inline.trim().to_string()),
+                            (false, _) => req.set_post_script(inline.trim().to_string()),
+                        }
+                        continue;
+                    }
+
                     if line.starts_with("M:") {
                         line = line.replace("M:", "");
                         let parts = line.split("=").collect::<Vec<&str>>();
@@ -396,17 +1468,114 @@ impl LazyReq {
                         continue;
                     }
 
-                    request_body.push_str(&line.trim().to_string());
+                    request_body.push_str(line.trim());
                 }
             }
         }
-        if request_body != "" {
+        if let Some((kind, _)) = script {
+            panic!("missing END for the {}: script of {}", kind, last_id);
+        }
+        if !request_body.is_empty() {
             let req = self.requests.get_mut(&last_id).unwrap();
             req.set_body(request_body);
         }
+
+        for (context, line) in deferred {
+            self.parse_setting(context, &line);
+        }
+
+        if self.cookie_mode == "persist" {
+            self.cookies = CookieJar::persisted(&self.filename, &self.environment);
+        }
+    }
+
+    fn parse_setting(&mut self, context: &str, line: &str) {
+        // `SECRET name = ...` keeps the value (or hook result) out of the output
+        let (secret, line) = match line.trim_start().strip_prefix("SECRET ") {
+            Some(rest) if context == "VARS" || context == "HOOKS" => (true, rest),
+            _ => (false, line),
+        };
+        if secret {
+            let name = line.split("=").next().unwrap_or("").trim();
+            self.secret_names.insert(name.to_string());
+        }
+
+        if context == "VARS" {
+            let (name, value) = match line.split_once("=") {
+                Some(parts) => parts,
+                None => panic!("invalid variable provided {}", line),
+            };
+            let value = parse_value(value);
+            if secret {
+                self.secrets.add(value.as_str());
+            }
+
+            self.add_variable(name.trim().to_string(), value);
+        }
+        if context == "OAUTH2" {
+            let parts = line.split("=").collect::<Vec<&str>>();
+            if parts.len() < 2 {
+                panic!("invalid oauth2 setting provided {}", line);
+            }
+            let value = parse_value(&parts[1..].join("="));
+
+            self.oauth.set(parts[0].trim(), value);
+        }
+        if context == "TLS" {
+            let parts = line.split("=").collect::<Vec<&str>>();
+            if parts.len() != 2 {
+                panic!("invalid tls setting provided {}", line);
+            }
+            let value = self
+                .resolve_sources(&parse_value(parts[1]))
+                .unwrap_or_else(|e| panic!("{}", e));
+            let value = dynamic::unescape(&value);
+
+            self.tls.set(parts[0].trim(), value);
+        }
+        if context == "HOOKS" {
+            let (name, hook) = match line.split_once("=") {
+                Some(parts) => parts,
+                None => panic!("invalid hook provided {}", line),
+            };
+            self.add_hook(name.trim().to_string(), hook.trim().to_string());
+        }
+        if context == "PROXY" {
+            let parts = line.split("=").collect::<Vec<&str>>();
+            if parts.len() != 2 {
+                panic!("invalid proxy setting provided {}", line);
+            }
+            let value = self
+                .resolve_sources(&parse_value(parts[1]))
+                .unwrap_or_else(|e| panic!("{}", e));
+            let value = dynamic::unescape(&value);
+
+            self.proxy.set(parts[0].trim(), value);
+        }
+        if context == "DEFAULTS" {
+            let parts = line.split("=").collect::<Vec<&str>>();
+            if parts.len() < 2 {
+                panic!("invalid default provided {}", line);
+            }
+            let value = parts[1..].join("=");
+
+            match parts[0].trim() {
+                "timeout" => self.timeout = TimeoutPolicy::parse(value.trim()),
+                "retry" => self.retry = RetryPolicy::parse(value.trim()),
+                "redirects" => self.redirect = RedirectPolicy::parse(value.trim()),
+                "cookies" => match value.trim() {
+                    "on" | "off" | "persist" => self.cookie_mode = value.trim().to_string(),
+                    _ => panic!("invalid cookies default provided {}", line),
+                },
+                _ => panic!("invalid default provided {}", line),
+            }
+        }
     }
 
     fn add_request(&mut self, id: String, request: Request) {
+        if !self.requests.contains_key(&id) {
+            self.order.push(id.clone());
+        }
         self.requests.insert(id, request);
     }
 
@@ -418,3 +1587,168 @@ impl LazyReq {
         self.hooks.insert(id, value);
     }
 }
+
+/// A resolved request, ready to be sent.
+pub struct Prepared {
+    pub url: String,
+    pub generated: Vec<(String, String)>,
+    pub timeout: TimeoutPolicy,
+    pub retry: RetryPolicy,
+    pub redirect: RedirectPolicy,
+    pub use_cookies: bool,
+    client: Client,
+    outgoing: Outgoing,
+}
+
+impl Prepared {
+    fn script_request(&self) -> ScriptRequest {
+        ScriptRequest {
+            method: self.outgoing.method.to_string(),
+            url: self.outgoing.url.clone(),
+            headers: self
+                .outgoing
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        value.to_str().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect(),
+            body: self.outgoing.body.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Outgoing {
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: String,
+    form: Option<Vec<FormPart>>,
+}
+
+#[derive(Clone)]
+enum FormPart {
+    Text {
+        name: String,
+        content: String,
+    },
+    File {
+        name: String,
+        content: Vec<u8>,
+        file_name: String,
+        mime: String,
+    },
+}
+
+/// Forms can't be cloned, so they're rebuilt from the loaded parts on every attempt.
+fn build_form(parts: &[FormPart]) -> multipart::Form {
+    let mut m = multipart::Form::new();
+
+    for part in parts {
+        m = match part {
+            FormPart::Text { name, content } => m.text(name.clone(), content.clone()),
+            FormPart::File {
+                name,
+                content,
+                file_name,
+                mime,
+            } => {
+                let file_part = Part::bytes(content.clone())
+                    .file_name(file_name.clone())
+                    .mime_str(mime.as_str())
+                    .unwrap();
+                m.part(name.clone(), file_part)
+            }
+        };
+    }
+
+    m
+}
+
+fn format_failure(id: &str, error: String) -> String {
+    format!("{} {}\n", format!("[{}]", id).bold().red(), error.red())
+}
+
+/// Adds the cookies set along the redirects to the response's own, a login
+/// usually sets them on the redirect, not on the page after it.
+fn with_redirect_cookies(mut response: Response) -> Response {
+    let mut headers = HeaderMap::new();
+    for cookie in response.redirects.iter().flat_map(|hop| hop.cookies.iter()) {
+        if let Ok(value) = HeaderValue::from_str(cookie) {
+            headers.append(SET_COOKIE, value);
+        }
+    }
+    for (name, value) in response.headers.iter() {
+        headers.append(name, value.clone());
+    }
+    response.headers = headers;
+    response
+}
+
+fn format_saved(path: &str, size: usize) -> String {
+    format!(
+        "{} {} to {}\n",
+        "Saved".bold().green(),
+        binary::format_size(size),
+        path.bold()
+    )
+}
+
+/// Returns the environment a section like `VARS staging` is restricted to.
+fn section_environment(line: &str) -> String {
+    line.split_whitespace().nth(1).unwrap_or("").to_string()
+}
+
+fn parse_value(raw: &str) -> String {
+    let mut value = raw.trim().to_string();
+
+    if value.starts_with('"') && value.ends_with('"')
+        || value.starts_with("'") && value.ends_with("'")
+    {
+        value = value.replace('"', "").replace("'", "");
+    }
+
+    value
+}
+
+/// Reads `NAME=value` lines from a `.env` file, a missing file is empty.
+fn load_dotenv(path: &Path) -> HashMap<String, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return HashMap::new(),
+    };
+
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("#"))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once("=")?;
+            Some((name.trim().to_string(), parse_value(value)))
+        })
+        .collect()
+}
+
+/// Follows a dotted path like `data.token` into a JSON body, numbers and
+/// other non-string values are used as they're written.
+fn json_value(body: &str, path: &str) -> Option<String> {
+    value_at(&serde_json::from_str(body).ok()?, path)
+}
+
+/// Same as `json_value`, for JSON that's already parsed.
+fn value_at(value: &Value, path: &str) -> Option<String> {
+    let mut parsed = value;
+    for part in path.split(".") {
+        parsed = parsed.get(part)?;
+    }
+
+    match parsed {
+        Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
@@ -1,13 +1,32 @@
 use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::Arc;
 
-use config::Config;
+use config::{Command, Config};
 use lazyreq::LazyReq;
+use tls::TlsConfig;
 
+mod auth;
+mod bench;
+mod binary;
 mod cache;
 mod config;
+mod cookies;
+mod crypto;
+mod dynamic;
+mod graph;
 mod lazyreq;
+mod oauth;
+mod output;
+mod policy;
+mod prompt;
+mod proxy;
 mod request;
+mod response;
+mod script;
+mod secrets;
 mod timest;
+mod tls;
 
 #[tokio::main]
 async fn main() {
@@ -15,12 +34,60 @@ async fn main() {
 
     let config = Config::new(&args);
 
+    // colors only make sense on a terminal, CLICOLOR_FORCE still wins
+    if !io::stdout().is_terminal() && env::var_os("CLICOLOR_FORCE").is_none() {
+        colored::control::set_override(false);
+    }
+
+    if let Command::Cookies(action) = &config.command {
+        match action.as_str() {
+            "clear" => cookies::clear(&config.filename, &config.environment),
+            _ => cookies::list(&config.filename, &config.environment),
+        }
+        return;
+    }
+
+    if let Command::Cache(action) = &config.command {
+        let id = config.targets.first().map(|id| id.as_str()).unwrap_or("");
+        match action.as_str() {
+            "show" => cache::show(&config.filename, id),
+            "clear" => cache::clear(&config.filename, id),
+            "prune" => cache::prune(),
+            _ => cache::list(&config.filename),
+        }
+        return;
+    }
+
     let mut lazyreq = LazyReq::new();
+    lazyreq.set_environment(config.environment);
     lazyreq.from_file(config.filename);
-    
-    if config.export_curl {
-        lazyreq.export_curl(config.target).await;
+    lazyreq.set_tls(config.tls.or(TlsConfig::from_env()));
+    lazyreq.set_proxy(config.proxy);
+    lazyreq.set_show_secrets(config.show_secrets);
+    lazyreq.set_verbose(config.verbose);
+    lazyreq.set_output(config.output);
+    lazyreq.set_output_file(config.output_file);
+    lazyreq.set_remote_name(config.remote_name);
+    lazyreq.set_input(!config.no_input);
+    if let Some(redirect) = config.redirect {
+        lazyreq.set_redirect(redirect);
+    }
+
+    let targets = if config.all {
+        lazyreq.request_ids()
+    } else {
+        config.targets
+    };
+
+    if let Command::Bench = config.command {
+        bench::run(Arc::new(lazyreq), targets[0].clone(), config.bench).await;
+    } else if config.export_curl {
+        for target in targets {
+            lazyreq.export_curl(target).await;
+        }
+    } else if targets.len() == 1 {
+        lazyreq.do_request(targets[0].clone()).await;
     } else {
-        lazyreq.do_request(config.target).await;
+        Arc::new(lazyreq).do_requests(targets, config.jobs).await;
     }
 }
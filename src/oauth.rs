@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::cache::Cache;
+use crate::policy::TimeoutPolicy;
+
+pub const OAUTH_CACHE_ID: &str = "$oauth";
+
+#[derive(Clone)]
+pub struct OAuth2 {
+    pub token_url: String,
+    pub grant_type: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl OAuth2 {
+    pub fn default() -> OAuth2 {
+        OAuth2 {
+            token_url: "".to_string(),
+            grant_type: "client_credentials".to_string(),
+            client_id: "".to_string(),
+            client_secret: "".to_string(),
+            scopes: "".to_string(),
+            username: "".to_string(),
+            password: "".to_string(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        match key {
+            "token_url" | "url" => self.token_url = value,
+            "grant_type" | "grant" => self.grant_type = value,
+            "client_id" => self.client_id = value,
+            "client_secret" => self.client_secret = value,
+            "scope" | "scopes" => self.scopes = value,
+            "username" => self.username = value,
+            "password" => self.password = value,
+            _ => panic!("invalid oauth2 setting provided {}", key),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.token_url.is_empty()
+    }
+
+    /// Builds the form parameters for the configured grant, or for a refresh
+    /// when a refresh token is given.
+    pub fn params(&self, refresh_token: Option<&str>) -> HashMap<&str, String> {
+        let mut params = HashMap::new();
+
+        match refresh_token {
+            Some(token) => {
+                params.insert("grant_type", "refresh_token".to_string());
+                params.insert("refresh_token", token.to_string());
+            }
+            None => {
+                params.insert("grant_type", self.grant_type.clone());
+                if self.grant_type == "password" {
+                    params.insert("username", self.username.clone());
+                    params.insert("password", self.password.clone());
+                }
+            }
+        }
+
+        params.insert("client_id", self.client_id.clone());
+        if !self.client_secret.is_empty() {
+            params.insert("client_secret", self.client_secret.clone());
+        }
+        if !self.scopes.is_empty() {
+            params.insert("scope", self.scopes.clone());
+        }
+
+        params
+    }
+
+    /// The token url and grant parameters in a stable order.
+    fn definition(&self) -> String {
+        let mut params = self
+            .params(None)
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<String>>();
+        params.sort();
+
+        format!("{}\n{}", self.token_url, params.join("\n"))
+    }
+}
+
+async fn request_token(
+    client: &Client,
+    token_url: &str,
+    params: &HashMap<&str, String>,
+    timeout: &TimeoutPolicy,
+) -> Result<Value, Box<dyn Error>> {
+    let mut builder = client.post(token_url).form(params);
+    if let Some(total) = timeout.total {
+        builder = builder.timeout(total);
+    }
+    let response = builder.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+
+    if !status.is_success() {
+        return Err(format!("oauth2 token request failed with {}: {}", status, body).into());
+    }
+
+    Ok(serde_json::from_str(body.as_str())?)
+}
+
+/// Returns the current token response, fetching a new one (or refreshing the
+/// cached one) when there's no valid token in the cache. Token requests get
+/// the `DEFAULTS` total timeout like any other request.
+pub async fn get_token(
+    client: &Client,
+    oauth: &OAuth2,
+    timeout: &TimeoutPolicy,
+    filename: &str,
+    environment: &str,
+) -> Result<Value, Box<dyn Error>> {
+    let mut cacher = Cache::new(filename, environment, OAUTH_CACHE_ID, &oauth.definition());
+    if let Some(has) = cacher.get() {
+        return Ok(serde_json::from_str(has.as_str())?);
+    }
+
+    let token_url = oauth.token_url.as_str();
+
+    let stale: Option<Value> = cacher
+        .peek()
+        .and_then(|stale| serde_json::from_str(stale.as_str()).ok());
+    let refresh_token = stale
+        .as_ref()
+        .and_then(|stale| stale.get("refresh_token"))
+        .and_then(|token| token.as_str());
+
+    let token = match refresh_token {
+        Some(refresh_token) => {
+            let refreshed = request_token(
+                client,
+                token_url,
+                &oauth.params(Some(refresh_token)),
+                timeout,
+            )
+            .await
+            .ok();
+            match refreshed {
+                Some(token) => token,
+                None => request_token(client, token_url, &oauth.params(None), timeout).await?,
+            }
+        }
+        None => request_token(client, token_url, &oauth.params(None), timeout).await?,
+    };
+
+    let mut stored = token.clone();
+    if stored.get("refresh_token").is_none() {
+        // some servers don't rotate refresh tokens, so keep the previous one around
+        if let Some(previous) = refresh_token {
+            stored["refresh_token"] = Value::String(previous.to_string());
+        }
+    }
+
+    let expires_in = token
+        .get("expires_in")
+        .and_then(|e| e.as_u64())
+        .unwrap_or(0);
+    cacher.set(serde_json::to_string(&stored)?, expires_in);
+
+    Ok(token)
+}
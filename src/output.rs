@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Map, Value};
+
+use crate::binary;
+use crate::response::{self, Response};
+use crate::secrets::Secrets;
+
+/// How finished requests are printed, picked with `--output`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Colored, for people.
+    Text,
+    /// The body alone on stdout, the rest on stderr.
+    Raw,
+    /// One JSON object per request, in an array when there are several.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Option<OutputFormat> {
+        match value {
+            "text" => Some(OutputFormat::Text),
+            "raw" => Some(OutputFormat::Raw),
+            "json" => Some(OutputFormat::Json),
+            "ndjson" => Some(OutputFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// What a finished request prints, already masked. Bytes, since a raw
+/// body doesn't have to be text.
+pub struct Rendered {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Rendered {
+    pub fn stdout(text: String) -> Rendered {
+        Rendered {
+            stdout: text.into_bytes(),
+            stderr: Vec::new(),
+        }
+    }
+
+    pub fn stderr(text: String) -> Rendered {
+        Rendered {
+            stdout: Vec::new(),
+            stderr: text.into_bytes(),
+        }
+    }
+}
+
+/// Everything about a request and its response, header values are masked
+/// the same way they are in the text output. Binary bodies are base64
+/// encoded, and left out once saved to a file.
+pub fn response_json(
+    id: &str,
+    response: &Response,
+    saved: Option<&str>,
+    secrets: &Secrets,
+) -> Value {
+    let headers = |headers: &reqwest::header::HeaderMap| {
+        let map = response::joined_headers(headers)
+            .into_iter()
+            .map(|(name, value)| {
+                let value = secrets.mask_header(&name, &value);
+                (name, Value::String(value))
+            })
+            .collect::<Map<String, Value>>();
+        Value::Object(map)
+    };
+
+    let (body, encoding) = match (saved, binary::is_binary(&response.headers, &response.bytes)) {
+        (Some(_), _) => (Value::Null, Value::Null),
+        (None, true) => (STANDARD.encode(&response.bytes).into(), "base64".into()),
+        (None, false) => (response.body.clone().into(), "text".into()),
+    };
+
+    json!({
+        "id": id,
+        "request": {
+            "method": response.method,
+            "url": response.url,
+            "headers": headers(&response.sent_headers),
+        },
+        "redirects": response
+            .redirects
+            .iter()
+            .map(|redirect| json!({"status": redirect.status.as_u16(), "location": redirect.location}))
+            .collect::<Vec<Value>>(),
+        "status": response.status.as_u16(),
+        "reason": response.status.canonical_reason().unwrap_or(""),
+        "headers": headers(&response.headers),
+        "timing": {
+            "total_ms": (response.elapsed.as_secs_f64() * 1_000_000.0).round() / 1000.0,
+        },
+        "generated": response
+            .generated
+            .iter()
+            .map(|(expression, value)| (expression.clone(), Value::String(value.clone())))
+            .collect::<Map<String, Value>>(),
+        "size": response.bytes.len(),
+        "saved_to": saved,
+        "body": body,
+        "body_encoding": encoding,
+    })
+}
+
+pub fn failure_json(id: &str, error: &str) -> Value {
+    json!({"id": id, "error": error})
+}
+
+/// Prints the output of the `index`th of `count` requests, `json` wraps
+/// several of them in an array.
+pub fn print(format: OutputFormat, rendered: &Rendered, index: usize, count: usize) {
+    let mut stdout = io::stdout().lock();
+    match format {
+        OutputFormat::Json if count > 1 => {
+            let separator = if index == 0 { "[\n" } else { ",\n" };
+            let object = String::from_utf8_lossy(&rendered.stdout);
+            let _ = write!(stdout, "{}{}", separator, object.trim_end());
+            if index + 1 == count {
+                let _ = writeln!(stdout, "\n]");
+            }
+        }
+        _ => {
+            let _ = stdout.write_all(&rendered.stdout);
+        }
+    }
+    let _ = stdout.flush();
+
+    let _ = io::stderr().write_all(&rendered.stderr);
+}
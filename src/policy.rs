@@ -0,0 +1,400 @@
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
+use reqwest::header::{HeaderMap, CACHE_CONTROL, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::timest::{get_timestamp, parse_duration};
+
+const DEFAULT_RETRY_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+#[derive(Clone, Default)]
+pub struct TimeoutPolicy {
+    pub connect: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub count: u32,
+    pub statuses: Vec<u16>,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+#[derive(Clone)]
+pub struct RedirectPolicy {
+    pub max: usize,
+}
+
+/// How a hook result is cached, `ttl` is `None` when it's taken from the
+/// response itself.
+#[derive(Clone)]
+pub struct CachePolicy {
+    pub ttl: Option<Duration>,
+    pub statuses: Vec<String>,
+}
+
+impl TimeoutPolicy {
+    /// Parses `TIMEOUT: 30s` or `TIMEOUT: connect=2s total=30s`.
+    pub fn parse(value: &str) -> TimeoutPolicy {
+        let mut policy = TimeoutPolicy::default();
+
+        for option in value.split_whitespace() {
+            let (key, duration) = option.split_once("=").unwrap_or(("total", option));
+            let duration = match parse_duration(duration) {
+                Some(duration) => duration,
+                None => panic!("invalid timeout provided {}", option),
+            };
+
+            match key {
+                "connect" => policy.connect = Some(duration),
+                "total" => policy.total = Some(duration),
+                _ => panic!("invalid timeout provided {}", option),
+            }
+        }
+
+        policy
+    }
+
+    /// Fills every unset timeout with the one from `other`.
+    pub fn or(&self, other: &TimeoutPolicy) -> TimeoutPolicy {
+        TimeoutPolicy {
+            connect: self.connect.or(other.connect),
+            total: self.total.or(other.total),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn default() -> RetryPolicy {
+        RetryPolicy {
+            count: 0,
+            statuses: DEFAULT_RETRY_STATUSES.to_vec(),
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Parses `RETRY: 3` or `RETRY: count=3 statuses=502,503 backoff=1s max_backoff=20s`.
+    pub fn parse(value: &str) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+
+        for option in value.split_whitespace() {
+            let (key, val) = option.split_once("=").unwrap_or(("count", option));
+
+            match key {
+                "count" => {
+                    policy.count = val
+                        .parse::<u32>()
+                        .unwrap_or_else(|_| panic!("invalid retry count provided {}", option))
+                }
+                "statuses" => {
+                    policy.statuses = val
+                        .split(",")
+                        .map(|status| {
+                            status
+                                .trim()
+                                .parse::<u16>()
+                                .unwrap_or_else(|_| panic!("invalid retry status {}", status))
+                        })
+                        .collect()
+                }
+                "backoff" => {
+                    policy.backoff = parse_duration(val)
+                        .unwrap_or_else(|| panic!("invalid retry backoff provided {}", option))
+                }
+                "max_backoff" => {
+                    policy.max_backoff = parse_duration(val)
+                        .unwrap_or_else(|| panic!("invalid retry backoff provided {}", option))
+                }
+                _ => panic!("invalid retry option provided {}", option),
+            }
+        }
+
+        policy
+    }
+
+    pub fn should_retry_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status.as_u16())
+    }
+
+    /// Exponential backoff with jitter, a `Retry-After` header on 429 or
+    /// 503 responses takes precedence, up to `max_backoff`.
+    pub fn delay(&self, attempt: u32, status: Option<StatusCode>, headers: &HeaderMap) -> Duration {
+        let honors_retry_after = matches!(
+            status,
+            Some(StatusCode::TOO_MANY_REQUESTS) | Some(StatusCode::SERVICE_UNAVAILABLE)
+        );
+        if honors_retry_after {
+            let retry_after = headers
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            if let Some(seconds) = retry_after {
+                // a server asking for hours shouldn't hang the run
+                return Duration::from_secs(seconds).min(self.max_backoff);
+            }
+        }
+
+        let exponential = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+
+        exponential.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+impl RedirectPolicy {
+    pub fn default() -> RedirectPolicy {
+        RedirectPolicy {
+            max: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+
+    /// Parses `REDIRECTS: off` or `REDIRECTS: 3`.
+    pub fn parse(value: &str) -> RedirectPolicy {
+        match value.trim() {
+            "off" | "none" => RedirectPolicy { max: 0 },
+            max => RedirectPolicy {
+                max: max
+                    .parse::<usize>()
+                    .unwrap_or_else(|_| panic!("invalid redirects provided {}", value)),
+            },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max > 0
+    }
+}
+
+impl CachePolicy {
+    /// Parses the options after a hook, e.g. `$req.login 15m` or
+    /// `$req.login auto statuses=200,404`. Without a TTL nothing is cached.
+    pub fn parse(options: &[&str]) -> Option<CachePolicy> {
+        let ttl = options.first()?;
+        let mut policy = CachePolicy {
+            ttl: match *ttl {
+                "auto" => None,
+                ttl => Some(
+                    parse_duration(ttl)
+                        .unwrap_or_else(|| panic!("invalid hook ttl provided {}", ttl)),
+                ),
+            },
+            statuses: vec!["2xx".to_string()],
+        };
+
+        for option in &options[1..] {
+            match option.split_once("=") {
+                Some(("statuses", statuses)) => {
+                    policy.statuses = statuses
+                        .split(",")
+                        .map(|s| s.trim().to_lowercase())
+                        .collect()
+                }
+                _ => panic!("invalid hook option provided {}", option),
+            }
+        }
+
+        Some(policy)
+    }
+
+    /// Statuses are either exact (`404`) or a whole class (`2xx`).
+    pub fn should_cache(&self, status: u16) -> bool {
+        self.statuses
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("xx") {
+                Some(class) => class == (status / 100).to_string(),
+                None => allowed == &status.to_string(),
+            })
+    }
+
+    /// The fixed TTL, or one derived from an `expires_in` field, the `exp`
+    /// claim of a JWT in the body, or `Cache-Control: max-age`.
+    pub fn ttl_for(&self, headers: &HeaderMap, body: &str) -> Option<Duration> {
+        if self.ttl.is_some() {
+            return self.ttl;
+        }
+
+        let json: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+        let expires_in = json.get("expires_in").and_then(|value| match value {
+            Value::Number(number) => number.as_u64(),
+            Value::String(string) => string.parse::<u64>().ok(),
+            _ => None,
+        });
+        if let Some(expires_in) = expires_in {
+            return Some(Duration::from_secs(expires_in));
+        }
+
+        let tokens: Vec<&str> = match &json {
+            Value::Object(fields) => fields.values().filter_map(|value| value.as_str()).collect(),
+            _ => vec![body.trim()],
+        };
+        let expires_at = tokens.into_iter().filter_map(jwt_expiry).min();
+        if let Some(expires_at) = expires_at {
+            return Some(Duration::from_secs(
+                expires_at.saturating_sub(get_timestamp()),
+            ));
+        }
+
+        headers
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value
+                    .split(",")
+                    .filter_map(|directive| directive.trim().strip_prefix("max-age="))
+                    .find_map(|seconds| seconds.parse::<u64>().ok())
+            })
+            .map(Duration::from_secs)
+    }
+}
+
+/// Returns the `exp` claim of a JWT, without checking its signature.
+fn jwt_expiry(token: &str) -> Option<u64> {
+    let parts: Vec<&str> = token.split(".").collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let payload = URL_SAFE_NO_PAD
+        .decode(parts[1].trim_end_matches("="))
+        .ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    claims.get("exp")?.as_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy(options: &[&str]) -> CachePolicy {
+        CachePolicy::parse(options).unwrap()
+    }
+
+    /// An unsigned JWT with only an `exp` claim.
+    fn jwt(exp: u64) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(format!("{{\"exp\":{}}}", exp));
+        format!("eyJhbGciOiJub25lIn0.{}.sig", payload)
+    }
+
+    #[test]
+    fn parse_needs_a_ttl() {
+        assert!(CachePolicy::parse(&[]).is_none());
+        assert_eq!(policy(&["15m"]).ttl, Some(Duration::from_secs(900)));
+        assert_eq!(policy(&["auto"]).ttl, None);
+    }
+
+    #[test]
+    fn should_cache_only_2xx_by_default() {
+        let policy = policy(&["10s"]);
+
+        assert!(policy.should_cache(200));
+        assert!(policy.should_cache(204));
+        assert!(!policy.should_cache(301));
+        assert!(!policy.should_cache(401));
+        assert!(!policy.should_cache(500));
+    }
+
+    #[test]
+    fn should_cache_exact_statuses_and_classes() {
+        let policy = policy(&["10s", "statuses=201,4XX"]);
+
+        assert!(policy.should_cache(201));
+        assert!(policy.should_cache(404));
+        assert!(policy.should_cache(499));
+        assert!(!policy.should_cache(200));
+        assert!(!policy.should_cache(500));
+    }
+
+    #[test]
+    fn ttl_for_prefers_the_fixed_ttl() {
+        let body = r#"{"expires_in": 3600}"#;
+
+        assert_eq!(
+            policy(&["10s"]).ttl_for(&HeaderMap::new(), body),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn ttl_for_expires_in() {
+        let policy = policy(&["auto"]);
+
+        assert_eq!(
+            policy.ttl_for(&HeaderMap::new(), r#"{"expires_in": 3600}"#),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            policy.ttl_for(&HeaderMap::new(), r#"{"expires_in": "120"}"#),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn ttl_for_jwt_expiry() {
+        let policy = policy(&["auto"]);
+        let exp = get_timestamp() + 300;
+
+        let in_field = format!(r#"{{"access_token": "{}"}}"#, jwt(exp));
+        let ttl = policy.ttl_for(&HeaderMap::new(), &in_field).unwrap();
+        assert!(ttl <= Duration::from_secs(300) && ttl >= Duration::from_secs(298));
+
+        let raw = policy.ttl_for(&HeaderMap::new(), &jwt(exp)).unwrap();
+        assert!(raw <= Duration::from_secs(300) && raw >= Duration::from_secs(298));
+
+        // an expired token isn't cached at all
+        let expired = jwt(get_timestamp() - 60);
+        assert_eq!(
+            policy.ttl_for(&HeaderMap::new(), &expired),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn ttl_for_max_age() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("private, max-age=60"),
+        );
+
+        assert_eq!(
+            policy(&["auto"]).ttl_for(&headers, "{}"),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn ttl_for_nothing_to_go_by() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+        assert_eq!(
+            policy(&["auto"]).ttl_for(&headers, r#"{"token": "abc"}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn jwt_expiry_reads_the_exp_claim() {
+        assert_eq!(jwt_expiry(&jwt(1700000000)), Some(1700000000));
+    }
+
+    #[test]
+    fn jwt_expiry_ignores_anything_else() {
+        let no_exp = format!("a.{}.c", URL_SAFE_NO_PAD.encode(r#"{"sub":"me"}"#));
+
+        assert_eq!(jwt_expiry("not-a-jwt"), None);
+        assert_eq!(jwt_expiry("a.b.c.d"), None);
+        assert_eq!(jwt_expiry("a.!!!.c"), None);
+        assert_eq!(jwt_expiry(&no_exp), None);
+    }
+}
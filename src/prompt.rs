@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// Asks the user for values while a run is going, each name is asked only
+/// once and the answer is kept for the rest of the run.
+pub struct Prompter {
+    answers: Mutex<HashMap<String, String>>,
+    pub enabled: bool,
+}
+
+impl Prompter {
+    pub fn new() -> Prompter {
+        Prompter {
+            answers: Mutex::new(HashMap::new()),
+            enabled: true,
+        }
+    }
+
+    /// Returns `None` when there's no one to ask, i.e. with `--no-input` or
+    /// when stdin isn't a terminal, and there's no default either.
+    pub fn ask(&self, name: &str, default: Option<&str>, hidden: bool) -> Option<String> {
+        // held while asking so concurrent requests don't prompt over each other
+        let mut answers = self.answers.lock().unwrap();
+        if let Some(answer) = answers.get(name) {
+            return Some(answer.clone());
+        }
+
+        if !self.enabled || !io::stdin().is_terminal() {
+            return default.map(String::from);
+        }
+
+        let label = match default {
+            Some(default) if !hidden => format!("{} [{}]: ", name, default),
+            _ => format!("{}: ", name),
+        };
+        let answer = match hidden {
+            true => rpassword::prompt_password(label).ok()?,
+            false => {
+                eprint!("{}", label);
+                io::stderr().flush().ok()?;
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer).ok()?;
+                answer.trim_end_matches(['\r', '\n']).to_string()
+            }
+        };
+
+        let answer = match (answer.is_empty(), default) {
+            (true, Some(default)) => default.to_string(),
+            _ => answer,
+        };
+        answers.insert(name.to_string(), answer.clone());
+        Some(answer)
+    }
+}
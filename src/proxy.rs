@@ -0,0 +1,69 @@
+use std::error::Error;
+
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+
+#[derive(Clone, Default)]
+pub struct ProxyConfig {
+    pub url: Option<String>,
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn set(&mut self, key: &str, value: String) {
+        match key {
+            "url" | "proxy" => self.url = Some(value),
+            "no_proxy" => self.no_proxy = Some(value),
+            _ => panic!("invalid proxy setting provided {}", key),
+        }
+    }
+
+    /// Fills every unset setting with the one from `other`.
+    pub fn or(self, other: ProxyConfig) -> ProxyConfig {
+        ProxyConfig {
+            url: self.url.or(other.url),
+            no_proxy: self.no_proxy.or(other.no_proxy),
+        }
+    }
+
+    fn is_disabled(&self) -> bool {
+        matches!(self.url.as_deref(), Some("off") | Some("none"))
+    }
+
+    /// Without an explicit proxy reqwest keeps honoring `HTTP(S)_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY` from the environment.
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, Box<dyn Error>> {
+        if self.is_disabled() {
+            return Ok(builder.no_proxy());
+        }
+
+        match &self.url {
+            Some(url) => {
+                let no_proxy = match &self.no_proxy {
+                    Some(no_proxy) => NoProxy::from_string(no_proxy),
+                    None => NoProxy::from_env(),
+                };
+
+                Ok(builder.proxy(Proxy::all(url.as_str())?.no_proxy(no_proxy)))
+            }
+            None => Ok(builder),
+        }
+    }
+
+    pub fn curl_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if self.is_disabled() {
+            args.push("--noproxy \"*\"".to_string());
+            return args;
+        }
+
+        if let Some(url) = &self.url {
+            args.push(format!("-x \"{}\"", url));
+        }
+        if let Some(no_proxy) = &self.no_proxy {
+            args.push(format!("--noproxy \"{}\"", no_proxy));
+        }
+
+        args
+    }
+}
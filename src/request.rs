@@ -1,13 +1,25 @@
 use std::collections::HashMap;
 
+use regex::Regex;
 use reqwest::Method;
 
+use crate::policy::{RedirectPolicy, RetryPolicy, TimeoutPolicy};
+
 pub struct Request {
     pub method: String,
     pub path: String,
     pub headers: HashMap<String, String>,
     pub body: String,
     pub multipart: Vec<MultiPart>,
+    pub auth: String,
+    pub timeout: Option<TimeoutPolicy>,
+    pub retry: Option<RetryPolicy>,
+    pub redirect: Option<RedirectPolicy>,
+    pub cookies: bool,
+    /// Rhai scripts run right before the request is sent and right after
+    /// the response comes back.
+    pub pre_script: String,
+    pub post_script: String,
 }
 
 #[derive(Clone)]
@@ -24,6 +36,13 @@ impl Request {
             headers: HashMap::new(),
             body: "".to_string(),
             multipart: Vec::new(),
+            auth: "".to_string(),
+            timeout: None,
+            retry: None,
+            redirect: None,
+            cookies: true,
+            pre_script: "".to_string(),
+            post_script: "".to_string(),
         }
     }
 
@@ -47,9 +66,78 @@ impl Request {
             headers: HashMap::new(),
             body,
             multipart,
+            auth: "".to_string(),
+            timeout: None,
+            retry: None,
+            redirect: None,
+            cookies: true,
+            pre_script: "".to_string(),
+            post_script: "".to_string(),
         }
     }
 
+    /// Names of every `$variable` or `$hook` used by the request.
+    pub fn references(&self) -> Vec<String> {
+        let re = Regex::new(r"\$(\w+)").unwrap();
+
+        let mut fields = vec![&self.path, &self.body, &self.auth];
+        fields.extend(self.headers.values());
+        fields.extend(self.multipart.iter().map(|part| &part.content));
+
+        fields
+            .iter()
+            .flat_map(|field| re.captures_iter(field))
+            .map(|capture| capture[1].to_string())
+            .collect()
+    }
+
+    /// Names the request's PRE and POST scripts set on `vars`, either as
+    /// `vars.name = ..` or `vars["name"] = ..`.
+    pub fn script_vars(&self) -> Vec<String> {
+        let re = Regex::new(r#"vars(?:\.(\w+)|\[\s*"(\w+)"\s*\])\s*=([^=]|$)"#).unwrap();
+
+        [&self.pre_script, &self.post_script]
+            .iter()
+            .flat_map(|script| re.captures_iter(script))
+            .filter_map(|capture| capture.get(1).or(capture.get(2)))
+            .map(|name| name.as_str().to_string())
+            .collect()
+    }
+
+    /// Everything that makes up the request as sent, in a stable order.
+    pub fn definition(&self) -> String {
+        let mut headers = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<String>>();
+        headers.sort();
+
+        let multipart = self
+            .multipart
+            .iter()
+            .map(|part| format!("{}={}", part.name, part.content))
+            .collect::<Vec<String>>();
+
+        let mut definition = [
+            self.method.to_uppercase(),
+            self.path.clone(),
+            headers.join("\n"),
+            self.auth.clone(),
+            multipart.join("\n"),
+            self.body.clone(),
+        ]
+        .join("\n");
+
+        // a PRE script can change what's sent
+        if !self.pre_script.is_empty() {
+            definition.push('\n');
+            definition.push_str(&self.pre_script);
+        }
+
+        definition
+    }
+
     pub fn add_header(&mut self, name: String, value: String) {
         self.headers.insert(name, value);
     }
@@ -76,4 +164,32 @@ impl Request {
     pub fn set_body(&mut self, body: String) {
         self.body = body;
     }
+
+    pub fn set_auth(&mut self, auth: String) {
+        self.auth = auth;
+    }
+
+    pub fn set_timeout(&mut self, timeout: TimeoutPolicy) {
+        self.timeout = Some(timeout);
+    }
+
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.retry = Some(retry);
+    }
+
+    pub fn set_redirect(&mut self, redirect: RedirectPolicy) {
+        self.redirect = Some(redirect);
+    }
+
+    pub fn set_cookies(&mut self, cookies: bool) {
+        self.cookies = cookies;
+    }
+
+    pub fn set_pre_script(&mut self, script: String) {
+        self.pre_script = script;
+    }
+
+    pub fn set_post_script(&mut self, script: String) {
+        self.post_script = script;
+    }
 }
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+#[derive(Clone)]
+pub struct Response {
+    pub method: String,
+    pub status: StatusCode,
+    pub url: String,
+    /// Headers of the request as it was sent.
+    pub sent_headers: HeaderMap,
+    pub headers: HeaderMap,
+    /// The body decoded as text, see `bytes` for it as it came.
+    pub body: String,
+    pub bytes: Vec<u8>,
+    pub redirects: Vec<Redirect>,
+    /// Dynamic values like `$uuid` and the value they got.
+    pub generated: Vec<(String, String)>,
+    /// From sending the request until the whole body came back.
+    pub elapsed: Duration,
+}
+
+#[derive(Clone)]
+pub struct Redirect {
+    pub status: StatusCode,
+    pub location: String,
+    /// `Set-Cookie` values sent along with the redirect.
+    pub cookies: Vec<String>,
+}
+
+/// Header names and values, repeated headers are joined like they'd be on
+/// the wire.
+pub fn joined_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    let mut joined: Vec<(String, String)> = Vec::new();
+    for (name, value) in headers.iter() {
+        let value = value.to_str().unwrap_or_default();
+        // `HeaderMap` keeps the values of a name together
+        match joined.last_mut() {
+            Some((last, existing)) if last == name.as_str() => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            _ => joined.push((name.to_string(), value.to_string())),
+        }
+    }
+    joined
+}
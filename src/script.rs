@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::request::Request;
+use crate::response::{self, Response};
+use crate::timest::get_timestamp;
+
+// enough for small bits of logic, not for a script stuck in a loop
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+
+/// The request as a `PRE:` script sees it, after variables, hooks and
+/// dynamic values have been resolved.
+pub struct ScriptRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ScriptRequest {
+    pub fn from_request(req: &Request) -> ScriptRequest {
+        ScriptRequest {
+            method: req.method.to_uppercase(),
+            url: req.path.clone(),
+            headers: req
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            body: req.body.clone(),
+        }
+    }
+
+    pub fn apply(self, req: &mut Request) {
+        req.set_method(self.method);
+        req.set_path(self.url);
+        req.set_headers(self.headers.into_iter().collect());
+        req.set_body(self.body);
+    }
+}
+
+/// Rhai with no access to files, processes or the network, only the
+/// helpers registered here.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .disable_symbol("eval");
+
+    // stdout is for the response
+    engine.on_print(|text| eprintln!("{}", text));
+    engine.on_debug(|text, _, _| eprintln!("{}", text));
+
+    engine
+        .register_fn("fail", |message: &str| -> Result<(), Box<EvalAltResult>> {
+            Err(message.into())
+        })
+        .register_fn("sha256", |data: &str| auth::sha256_hex(data.as_bytes()))
+        .register_fn("hmac_sha256", |key: &str, data: &str| {
+            hex::encode(auth::hmac_sha256(key.as_bytes(), data))
+        })
+        .register_fn("base64", |data: &str| STANDARD.encode(data))
+        .register_fn("urlencode", |data: &str| auth::uri_encode(data, true))
+        .register_fn("uuid", || Uuid::new_v4().to_string())
+        .register_fn("now", || get_timestamp() as i64);
+
+    engine
+}
+
+/// Runs a `PRE:` script, which can change the request before it's sent.
+pub fn run_pre(
+    script: &str,
+    request: &mut ScriptRequest,
+    vars: &Mutex<HashMap<String, String>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut scope = Scope::new();
+    scope.push("request", request_map(request));
+    scope.push("vars", vars_map(vars));
+
+    engine()
+        .run_with_scope(&mut scope, script)
+        .map_err(|e| format!("PRE script failed: {}", describe(&e)))?;
+
+    let map = scope.get_value::<Map>("request").unwrap_or_default();
+    let field = |name: &str| map.get(name).map(|value| value.to_string());
+    request.method = field("method").unwrap_or_default().to_uppercase();
+    request.url = field("url").unwrap_or_default();
+    request.body = field("body").unwrap_or_default();
+    request.headers = map
+        .get("headers")
+        .and_then(|headers| headers.clone().try_cast::<Map>())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+    store_vars(&scope, vars);
+    Ok(())
+}
+
+/// Runs a `POST:` script, which can look at the response, set variables for
+/// the requests after it and fail the run.
+pub fn run_post(
+    script: &str,
+    request: &ScriptRequest,
+    response: &Response,
+    vars: &Mutex<HashMap<String, String>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut scope = Scope::new();
+    scope.push_constant("request", request_map(request));
+    scope.push_constant("response", response_map(response));
+    scope.push("vars", vars_map(vars));
+
+    engine()
+        .run_with_scope(&mut scope, script)
+        .map_err(|e| format!("POST script failed: {}", describe(&e)))?;
+
+    store_vars(&scope, vars);
+    Ok(())
+}
+
+fn request_map(request: &ScriptRequest) -> Map {
+    let headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.as_str().into(), value.into()))
+        .collect::<Map>();
+
+    let mut map = Map::new();
+    map.insert("method".into(), request.method.clone().into());
+    map.insert("url".into(), request.url.clone().into());
+    map.insert("headers".into(), headers.into());
+    map.insert("body".into(), request.body.clone().into());
+    map
+}
+
+fn response_map(response: &Response) -> Map {
+    let status = response.status.as_u16() as i64;
+
+    let headers: Map = response::joined_headers(&response.headers)
+        .into_iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect();
+
+    // `()` when the body isn't JSON
+    let json = serde_json::from_str::<serde_json::Value>(&response.body)
+        .ok()
+        .and_then(|json| rhai::serde::to_dynamic(json).ok())
+        .unwrap_or(Dynamic::UNIT);
+
+    let mut map = Map::new();
+    map.insert("status".into(), status.into());
+    map.insert("headers".into(), headers.into());
+    map.insert("body".into(), response.body.clone().into());
+    map.insert("json".into(), json);
+    map
+}
+
+fn vars_map(vars: &Mutex<HashMap<String, String>>) -> Map {
+    vars.lock()
+        .unwrap()
+        .iter()
+        .map(|(name, value)| (name.as_str().into(), value.into()))
+        .collect()
+}
+
+fn store_vars(scope: &Scope, vars: &Mutex<HashMap<String, String>>) {
+    let map = scope.get_value::<Map>("vars").unwrap_or_default();
+    let mut vars = vars.lock().unwrap();
+    for (name, value) in map {
+        vars.insert(name.to_string(), value.to_string());
+    }
+}
+
+/// The message given to `fail` or `throw` as is, anything else with its
+/// position in the script.
+fn describe(error: &EvalAltResult) -> String {
+    match error {
+        EvalAltResult::ErrorRuntime(value, _) => value.to_string(),
+        error => error.to_string(),
+    }
+}
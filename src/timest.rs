@@ -1,4 +1,4 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn get_timestamp() -> u64 {
     SystemTime::now()
@@ -15,3 +15,75 @@ pub fn is_older_than(timestamp: u64) -> bool {
 pub fn add_seconds(timestamp: u64, seconds: u64) -> u64 {
     timestamp + seconds
 }
+
+/// Formats a unix timestamp as a UTC `YYYYMMDDTHHMMSSZ` string.
+pub fn format_basic_iso8601(timestamp: u64) -> String {
+    format_iso8601(timestamp).replace(['-', ':'], "")
+}
+
+/// Formats a unix timestamp as a UTC `YYYY-MM-DDTHH:MM:SSZ` string.
+pub fn format_iso8601(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
+/// Parses durations like `500ms`, `30s`, `15m`, `1h` or `1d`, plain numbers
+/// are seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount = amount.parse::<f64>().ok()?;
+
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" => amount * 60.0,
+        "h" => amount * 3600.0,
+        "d" => amount * 86400.0,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Formats a number of seconds as e.g. `42s`, `5m 3s` or `2h 10m`.
+pub fn format_seconds(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = seconds % 86400 / 3600;
+    let minutes = seconds % 3600 / 60;
+    let seconds = seconds % 60;
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use openssl::pkey::PKey;
+use reqwest::tls::{Certificate, Identity, Version};
+use reqwest::ClientBuilder;
+
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    pub ca_bundle: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub cert_password: Option<String>,
+    pub insecure: Option<bool>,
+    pub min_version: Option<String>,
+}
+
+impl TlsConfig {
+    /// Reads the `LAZYREQ_*` environment variables.
+    pub fn from_env() -> TlsConfig {
+        TlsConfig {
+            ca_bundle: env::var("LAZYREQ_CACERT").ok(),
+            cert: env::var("LAZYREQ_CERT").ok(),
+            key: env::var("LAZYREQ_KEY").ok(),
+            cert_password: env::var("LAZYREQ_CERT_PASSWORD").ok(),
+            insecure: env::var("LAZYREQ_INSECURE")
+                .ok()
+                .map(|value| value == "1" || value == "true"),
+            min_version: env::var("LAZYREQ_TLS_MIN").ok(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        match key {
+            "ca" | "cacert" | "ca_bundle" => self.ca_bundle = Some(value),
+            "cert" => self.cert = Some(value),
+            "key" => self.key = Some(value),
+            "password" | "cert_password" => self.cert_password = Some(value),
+            "insecure" => self.insecure = Some(value == "true"),
+            "min_version" => self.min_version = Some(value),
+            _ => panic!("invalid tls setting provided {}", key),
+        }
+    }
+
+    /// Fills every unset setting with the one from `other`.
+    pub fn or(self, other: TlsConfig) -> TlsConfig {
+        TlsConfig {
+            ca_bundle: self.ca_bundle.or(other.ca_bundle),
+            cert: self.cert.or(other.cert),
+            key: self.key.or(other.key),
+            cert_password: self.cert_password.or(other.cert_password),
+            insecure: self.insecure.or(other.insecure),
+            min_version: self.min_version.or(other.min_version),
+        }
+    }
+
+    fn is_pkcs12(&self) -> bool {
+        self.cert
+            .as_ref()
+            .is_some_and(|cert| cert.ends_with(".p12") || cert.ends_with(".pfx"))
+    }
+
+    pub fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, Box<dyn Error>> {
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let pem = fs::read(ca_bundle)?;
+            for cert in openssl::x509::X509::stack_from_pem(&pem)? {
+                builder = builder.add_root_certificate(Certificate::from_pem(&cert.to_pem()?)?);
+            }
+        }
+
+        if let Some(cert) = &self.cert {
+            let identity = if self.is_pkcs12() {
+                let password = self.cert_password.clone().unwrap_or_default();
+                Identity::from_pkcs12_der(&fs::read(cert)?, password.as_str())?
+            } else {
+                // the key may live in the certificate file itself
+                let key_file = self.key.as_ref().unwrap_or(cert);
+                let key = PKey::private_key_from_pem(&fs::read(key_file)?)?;
+                Identity::from_pkcs8_pem(&fs::read(cert)?, &key.private_key_to_pem_pkcs8()?)?
+            };
+            builder = builder.identity(identity);
+        }
+
+        if self.insecure.unwrap_or(false) {
+            builder = builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+
+        if let Some(min_version) = &self.min_version {
+            let version = match min_version.as_str() {
+                "1.0" => Version::TLS_1_0,
+                "1.1" => Version::TLS_1_1,
+                "1.2" => Version::TLS_1_2,
+                "1.3" => Version::TLS_1_3,
+                _ => return Err(format!("invalid tls version provided {}", min_version).into()),
+            };
+            builder = builder.min_tls_version(version);
+        }
+
+        Ok(builder)
+    }
+
+    pub fn curl_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(ca_bundle) = &self.ca_bundle {
+            args.push(format!("--cacert \"{}\"", ca_bundle));
+        }
+
+        if let Some(cert) = &self.cert {
+            let password = match &self.cert_password {
+                Some(password) => format!(":{}", password),
+                None => "".to_string(),
+            };
+            if self.is_pkcs12() {
+                args.push(format!("--cert-type P12 --cert \"{}{}\"", cert, password));
+            } else {
+                args.push(format!("--cert \"{}{}\"", cert, password));
+                if let Some(key) = &self.key {
+                    args.push(format!("--key \"{}\"", key));
+                }
+            }
+        }
+
+        if self.insecure.unwrap_or(false) {
+            args.push("-k".to_string());
+        }
+
+        if let Some(min_version) = &self.min_version {
+            args.push(format!("--tlsv{}", min_version));
+        }
+
+        args
+    }
+}